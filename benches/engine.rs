@@ -0,0 +1,175 @@
+//! Throughput benchmarks. Every benchmark reports records per second and
+//! bytes per second of CSV input (or output, for serialization).
+//!
+//! Run with `cargo bench`; `cargo bench -- dispute` filters by name. The
+//! serde-only comparisons need `--features bench-serde`.
+
+use std::fs;
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+use payments_engine::account::{OutputConfig, OutputFormat};
+use payments_engine::amount::Amount;
+use payments_engine::engine::PaymentEngine;
+use payments_engine::generate::{Generator, GeneratorConfig};
+#[cfg(feature = "bench-serde")]
+use payments_engine::io::process_csv_serde;
+use payments_engine::io::{process_csv, write_accounts_with, write_transactions};
+use payments_engine::transaction::TransactionInput;
+
+fn large_csv() -> Vec<u8> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/example_csvs/large.csv");
+    fs::read(path).expect("example_csvs/large.csv is part of the repository")
+}
+
+fn parse(csv_data: &[u8]) -> Vec<TransactionInput> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv_data)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("benchmark input parses")
+}
+
+/// A generated workload as CSV, so byte counts are comparable with `large.csv`.
+fn generated(config: GeneratorConfig) -> Vec<u8> {
+    let mut csv_data = Vec::new();
+    write_transactions(&mut csv_data, Generator::new(config)).unwrap();
+    csv_data
+}
+
+fn run(records: &[TransactionInput]) -> PaymentEngine {
+    let mut engine = PaymentEngine::new();
+    for record in records {
+        let _ = engine.process(record);
+    }
+    engine
+}
+
+fn throughput(records: usize, bytes: usize) -> Throughput {
+    Throughput::ElementsAndBytes {
+        elements: records as u64,
+        bytes: bytes as u64,
+    }
+}
+
+fn engine_path(c: &mut Criterion) {
+    let csv_data = large_csv();
+    let records = parse(&csv_data);
+
+    let mut group = c.benchmark_group("engine");
+    group.sample_size(20);
+    group.throughput(throughput(records.len(), csv_data.len()));
+    group.bench_function("large_csv_records", |b| b.iter(|| run(black_box(&records))));
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let csv_data = large_csv();
+    let records = parse(&csv_data).len();
+
+    let mut group = c.benchmark_group("end_to_end");
+    group.sample_size(20);
+    group.throughput(throughput(records, csv_data.len()));
+    group.bench_function("large_csv", |b| {
+        b.iter(|| process_csv(black_box(csv_data.as_slice())).unwrap())
+    });
+    #[cfg(feature = "bench-serde")]
+    group.bench_function("serde", |b| {
+        b.iter(|| process_csv_serde(black_box(csv_data.as_slice()), PaymentEngine::new()).unwrap())
+    });
+    group.finish();
+}
+
+fn dispute_heavy(c: &mut Criterion) {
+    let csv_data = generated(GeneratorConfig {
+        seed: 1,
+        clients: 1000,
+        transactions: 100_000,
+        dispute_rate: 0.5,
+        chargeback_rate: 0.1,
+        ..GeneratorConfig::default()
+    });
+    let records = parse(&csv_data);
+
+    let mut group = c.benchmark_group("dispute_heavy");
+    group.sample_size(20);
+    group.throughput(throughput(records.len(), csv_data.len()));
+    group.bench_function("records", |b| b.iter(|| run(black_box(&records))));
+    group.bench_function("csv", |b| {
+        b.iter(|| process_csv(black_box(csv_data.as_slice())).unwrap())
+    });
+    #[cfg(feature = "bench-serde")]
+    group.bench_function("serde", |b| {
+        b.iter(|| process_csv_serde(black_box(csv_data.as_slice()), PaymentEngine::new()).unwrap())
+    });
+    group.finish();
+}
+
+fn write_accounts(c: &mut Criterion) {
+    let engine = run(&parse(&generated(GeneratorConfig {
+        seed: 2,
+        clients: 100_000,
+        transactions: 200_000,
+        ..GeneratorConfig::default()
+    })));
+    let accounts = engine.accounts().count();
+
+    let mut group = c.benchmark_group("write_accounts");
+    for (name, format) in [("csv", OutputFormat::Csv), ("json", OutputFormat::Json)] {
+        let config = OutputConfig {
+            format,
+            ..OutputConfig::default()
+        };
+        let mut output = Vec::new();
+        write_accounts_with(&mut output, &engine, &config).unwrap();
+
+        group.throughput(throughput(accounts, output.len()));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut output = Vec::with_capacity(output.len());
+                write_accounts_with(&mut output, black_box(&engine), &config).unwrap();
+                output
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Bytes are those of the `large.csv` rows the amounts come from.
+fn amount_arithmetic(c: &mut Criterion) {
+    let csv_data = large_csv();
+    let amounts: Vec<Amount> = parse(&csv_data)
+        .iter()
+        .filter_map(|record| record.amount)
+        .map(Amount::from)
+        .collect();
+
+    let mut group = c.benchmark_group("amount");
+    group.throughput(throughput(amounts.len(), csv_data.len()));
+    group.bench_function("checked_add_sub", |b| {
+        b.iter(|| {
+            black_box(&amounts)
+                .iter()
+                .try_fold(Amount::ZERO, |balance, &amount| {
+                    balance
+                        .checked_add(amount)?
+                        .checked_sub(amount)?
+                        .checked_add(amount)
+                })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    amount_arithmetic,
+    engine_path,
+    end_to_end,
+    dispute_heavy,
+    write_accounts
+);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine::io::process_csv;
+
+// Arbitrary bytes through the same flexible, trimming CSV reader the CLI uses.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(engine) = process_csv(data) {
+        if let Err(violation) = engine.check_invariants() {
+            panic!("{violation}");
+        }
+    }
+});
@@ -0,0 +1,92 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::engine::{EngineConfig, HoldExpiry, PaymentEngine, PrecisionPolicy};
+use payments_engine::transaction::{ClientId, TransactionInput, TransactionType, TxId};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    Refund,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Record {
+    kind: Kind,
+    // Small id spaces so disputes, captures and refunds hit earlier transactions.
+    client: u8,
+    tx: u8,
+    amount: Option<(i64, u8)>,
+    timestamp: Option<u16>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    precision: u8,
+    max_amount: Option<i64>,
+    hold_expiry: Option<u8>,
+    records: Vec<Record>,
+}
+
+impl From<&Record> for TransactionInput {
+    fn from(record: &Record) -> Self {
+        let tx_type = match record.kind {
+            Kind::Deposit => TransactionType::Deposit,
+            Kind::Withdrawal => TransactionType::Withdrawal,
+            Kind::Dispute => TransactionType::Dispute,
+            Kind::Resolve => TransactionType::Resolve,
+            Kind::Chargeback => TransactionType::Chargeback,
+            Kind::Authorize => TransactionType::Authorize,
+            Kind::Capture => TransactionType::Capture,
+            Kind::Void => TransactionType::Void,
+            Kind::Refund => TransactionType::Refund,
+            Kind::Open => TransactionType::Open,
+            Kind::Close => TransactionType::Close,
+        };
+        TransactionInput {
+            tx_type,
+            client: ClientId(u64::from(record.client % 8)),
+            tx: TxId(u64::from(record.tx % 32)),
+            amount: record
+                .amount
+                .map(|(mantissa, scale)| Decimal::new(mantissa, u32::from(scale % 29))),
+            timestamp: record.timestamp.map(u64::from),
+            metadata: None,
+            provenance: None,
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let precision = match input.precision % 3 {
+        0 => PrecisionPolicy::Reject,
+        1 => PrecisionPolicy::RoundHalfEven,
+        _ => PrecisionPolicy::Truncate,
+    };
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        precision,
+        max_amount: input.max_amount.map(Decimal::from),
+        hold_expiry: input
+            .hold_expiry
+            .map_or(HoldExpiry::Never, |n| HoldExpiry::AfterTransactions(u64::from(n % 8))),
+        ..EngineConfig::default()
+    });
+
+    for record in &input.records {
+        let _ = engine.process(&TransactionInput::from(record));
+        if let Err(violation) = engine.check_invariants() {
+            panic!("{violation}");
+        }
+    }
+});
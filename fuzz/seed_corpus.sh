@@ -0,0 +1,18 @@
+#!/bin/sh
+# Builds the process_csv seed corpus from example_csvs/. large.csv is split
+# into 100-row chunks that each keep the header.
+set -eu
+
+cd "$(dirname "$0")"
+corpus=corpus/process_csv
+mkdir -p "$corpus"
+
+cp ../example_csvs/sample.csv "$corpus/sample.csv"
+cp ../example_csvs/missing_columns.csv "$corpus/missing_columns.csv"
+
+header=$(head -n 1 ../example_csvs/large.csv)
+tail -n +2 ../example_csvs/large.csv | head -n 2000 | split -l 100 - "$corpus/large-"
+for chunk in "$corpus"/large-??; do
+    { echo "$header"; cat "$chunk"; } > "$chunk.csv"
+    rm "$chunk"
+done
@@ -1,59 +1,188 @@
-use rust_decimal::Decimal;
-use rust_decimal::dec;
-use serde::Serialize;
+use std::str::FromStr;
 
-#[derive(Debug)]
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::amount::Amount;
+use crate::error::PaymentError;
+use crate::transaction::ClientId;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
-    pub client: u16,
-    pub available: Decimal,
-    pub held: Decimal,
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
     pub locked: bool,
+    /// Set by a `close` transaction. Closed accounts accept no further
+    /// transactions and always have zero balances.
+    pub closed: bool,
 }
 
 impl Account {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: ClientId) -> Self {
         Self {
             client,
-            available: dec!(0),
-            held: dec!(0),
+            available: Amount::ZERO,
+            held: Amount::ZERO,
             locked: false,
+            closed: false,
+        }
+    }
+
+    /// `None` if `available + held` overflows, which the engine never
+    /// allows but balances loaded from an event log can.
+    pub fn total(&self) -> Option<Amount> {
+        self.available.checked_add(self.held)
+    }
+
+    pub fn checked_adjust(&self, available: Amount, held: Amount) -> Option<(Amount, Amount)> {
+        let available = self.available.checked_add(available)?;
+        let held = self.held.checked_add(held)?;
+        available.checked_add(held)?;
+        Some((available, held))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!(
+                "unknown output format `{other}` (expected csv or json)"
+            )),
+        }
+    }
+}
+
+/// Controls how account balances are rendered.
+///
+/// `scale: None` prints the full internal precision. `numeric` emits JSON
+/// numbers instead of strings and is ignored for CSV output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    pub scale: Option<u32>,
+    pub rounding: RoundingStrategy,
+    pub numeric: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Csv,
+            scale: Some(4),
+            rounding: RoundingStrategy::ToZero,
+            numeric: false,
+        }
+    }
+}
+
+impl OutputConfig {
+    pub fn format_amount(&self, value: Decimal) -> String {
+        match self.scale {
+            Some(scale) => {
+                let mut rounded = value.round_dp_with_strategy(scale, self.rounding);
+                rounded.rescale(scale);
+                rounded.to_string()
+            }
+            None => value.to_string(),
         }
     }
+}
 
-    pub fn total(&self) -> Decimal {
-        self.available + self.held
+pub fn parse_rounding(s: &str) -> Result<RoundingStrategy, String> {
+    match s {
+        "half-even" => Ok(RoundingStrategy::MidpointNearestEven),
+        "half-up" => Ok(RoundingStrategy::MidpointAwayFromZero),
+        "half-down" => Ok(RoundingStrategy::MidpointTowardZero),
+        "truncate" => Ok(RoundingStrategy::ToZero),
+        "floor" => Ok(RoundingStrategy::ToNegativeInfinity),
+        "ceil" => Ok(RoundingStrategy::ToPositiveInfinity),
+        other => Err(format!(
+            "unknown rounding mode `{other}` (expected half-even, half-up, half-down, truncate, floor or ceil)"
+        )),
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct AccountOutput {
-    pub client: u16,
+    pub client: ClientId,
     pub available: String,
     pub held: String,
     pub total: String,
     pub locked: bool,
+    numeric: bool,
 }
 
-impl From<&Account> for AccountOutput {
-    fn from(account: &Account) -> Self {
-        Self {
+impl AccountOutput {
+    pub fn with_config(account: &Account, config: &OutputConfig) -> Result<Self, PaymentError> {
+        // Total is derived from the unrounded balances and rounded once, so it
+        // never accumulates the rounding error of both components.
+        let total = account
+            .total()
+            .ok_or(PaymentError::BalanceOverflow(account.client))?;
+        Ok(Self {
             client: account.client,
-            available: format!("{:.4}", account.available),
-            held: format!("{:.4}", account.held),
-            total: format!("{:.4}", account.total()),
+            available: config.format_amount(account.available.to_decimal()),
+            held: config.format_amount(account.held.to_decimal()),
+            total: config.format_amount(total.to_decimal()),
             locked: account.locked,
+            numeric: config.numeric && config.format == OutputFormat::Json,
+        })
+    }
+}
+
+impl TryFrom<&Account> for AccountOutput {
+    type Error = PaymentError;
+
+    fn try_from(account: &Account) -> Result<Self, PaymentError> {
+        Self::with_config(account, &OutputConfig::default())
+    }
+}
+
+impl Serialize for AccountOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AccountOutput", 5)?;
+        state.serialize_field("client", &self.client)?;
+        if self.numeric {
+            for (name, value) in [
+                ("available", &self.available),
+                ("held", &self.held),
+                ("total", &self.total),
+            ] {
+                let number =
+                    serde_json::Number::from_str(value).map_err(serde::ser::Error::custom)?;
+                state.serialize_field(name, &number)?;
+            }
+        } else {
+            state.serialize_field("available", &self.available)?;
+            state.serialize_field("held", &self.held)?;
+            state.serialize_field("total", &self.total)?;
         }
+        state.serialize_field("locked", &self.locked)?;
+        state.end()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_decimal::dec;
 
     #[test]
     fn new_account_is_zeroed() {
-        let account = Account::new(1);
-        assert_eq!(account.client, 1);
+        let account = Account::new(ClientId(1));
+        assert_eq!(account.client, ClientId(1));
         assert_eq!(account.available, dec!(0));
         assert_eq!(account.held, dec!(0));
         assert!(!account.locked);
@@ -62,39 +191,163 @@ mod tests {
     #[test]
     fn total_equals_available_plus_held() {
         let account = Account {
-            client: 1,
-            available: dec!(10),
-            held: dec!(5),
+            client: ClientId(1),
+            available: dec!(10).into(),
+            held: dec!(5).into(),
             locked: false,
+            closed: false,
         };
-        assert_eq!(account.total(), dec!(15));
+        assert_eq!(account.total().unwrap(), dec!(15));
+    }
+
+    #[test]
+    fn checked_adjust_rejects_overflowing_total() {
+        let account = Account {
+            client: ClientId(1),
+            available: Decimal::MAX.into(),
+            held: dec!(0).into(),
+            locked: false,
+            closed: false,
+        };
+        let (zero, one) = (Amount::ZERO, Amount::from(dec!(1)));
+        assert_eq!(account.checked_adjust(one, zero), None);
+        assert_eq!(account.checked_adjust(zero, one), None);
+        assert_eq!(
+            account.checked_adjust(-one, one),
+            Some(((Decimal::MAX - dec!(1)).into(), one))
+        );
     }
 
     #[test]
     fn output_formats_four_decimal_places() {
         let account = Account {
-            client: 1,
-            available: dec!(1.5),
-            held: dec!(0),
+            client: ClientId(1),
+            available: dec!(1.5).into(),
+            held: dec!(0).into(),
             locked: false,
+            closed: false,
         };
-        let output = AccountOutput::from(&account);
+        let output = AccountOutput::try_from(&account).unwrap();
         assert_eq!(output.available, "1.5000");
         assert_eq!(output.held, "0.0000");
         assert_eq!(output.total, "1.5000");
         assert!(!output.locked);
     }
 
+    #[test]
+    fn output_default_truncates_extra_digits() {
+        let config = OutputConfig {
+            scale: Some(2),
+            ..OutputConfig::default()
+        };
+        let account = Account {
+            client: ClientId(1),
+            available: dec!(1.9999).into(),
+            held: dec!(0).into(),
+            locked: false,
+            closed: false,
+        };
+        let output = AccountOutput::with_config(&account, &config).unwrap();
+        assert_eq!(output.available, "1.99");
+    }
+
+    #[test]
+    fn output_two_decimals_bankers_rounding() {
+        let config = OutputConfig {
+            scale: Some(2),
+            rounding: RoundingStrategy::MidpointNearestEven,
+            ..OutputConfig::default()
+        };
+        let account = Account {
+            client: ClientId(1),
+            available: dec!(1.005).into(),
+            held: dec!(2.015).into(),
+            locked: false,
+            closed: false,
+        };
+        let output = AccountOutput::with_config(&account, &config).unwrap();
+        assert_eq!(output.available, "1.00");
+        assert_eq!(output.held, "2.02");
+        assert_eq!(output.total, "3.02");
+    }
+
+    #[test]
+    fn output_total_rounded_from_unrounded_balances() {
+        let config = OutputConfig {
+            scale: Some(2),
+            rounding: RoundingStrategy::ToZero,
+            ..OutputConfig::default()
+        };
+        let account = Account {
+            client: ClientId(1),
+            available: dec!(0.006).into(),
+            held: dec!(0.006).into(),
+            locked: false,
+            closed: false,
+        };
+        let output = AccountOutput::with_config(&account, &config).unwrap();
+        assert_eq!(output.available, "0.00");
+        assert_eq!(output.held, "0.00");
+        assert_eq!(output.total, "0.01");
+    }
+
+    #[test]
+    fn output_full_precision() {
+        let config = OutputConfig {
+            scale: None,
+            ..OutputConfig::default()
+        };
+        let account = Account {
+            client: ClientId(1),
+            available: dec!(1.1230).into(),
+            held: dec!(0).into(),
+            locked: false,
+            closed: false,
+        };
+        let output = AccountOutput::with_config(&account, &config).unwrap();
+        assert_eq!(output.available, "1.123");
+        assert_eq!(output.held, "0");
+    }
+
+    #[test]
+    fn output_numeric_json() {
+        let config = OutputConfig {
+            format: OutputFormat::Json,
+            numeric: true,
+            ..OutputConfig::default()
+        };
+        let account = Account {
+            client: ClientId(7),
+            available: dec!(1.5).into(),
+            held: dec!(0).into(),
+            locked: false,
+            closed: false,
+        };
+        let output = AccountOutput::with_config(&account, &config).unwrap();
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"client":7,"available":1.5000,"held":0.0000,"total":1.5000,"locked":false}"#
+        );
+    }
+
+    #[test]
+    fn parse_rounding_modes() {
+        assert_eq!(parse_rounding("half-even"), Ok(RoundingStrategy::MidpointNearestEven));
+        assert_eq!(parse_rounding("truncate"), Ok(RoundingStrategy::ToZero));
+        assert!(parse_rounding("banker").is_err());
+    }
+
     #[test]
     fn output_formats_round_numbers() {
         let account = Account {
-            client: 2,
-            available: dec!(3),
-            held: dec!(2),
+            client: ClientId(2),
+            available: dec!(3).into(),
+            held: dec!(2).into(),
             locked: true,
+            closed: false,
         };
-        let output = AccountOutput::from(&account);
-        assert_eq!(output.client, 2);
+        let output = AccountOutput::try_from(&account).unwrap();
+        assert_eq!(output.client, ClientId(2));
         assert_eq!(output.available, "3.0000");
         assert_eq!(output.held, "2.0000");
         assert_eq!(output.total, "5.0000");
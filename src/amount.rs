@@ -0,0 +1,213 @@
+use std::fmt;
+use std::ops::Neg;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::engine::AMOUNT_SCALE;
+
+/// A monetary amount with [`AMOUNT_SCALE`] fixed decimal places.
+///
+/// By default this is an `i128` count of minor units (1/10000), so balance
+/// arithmetic is plain integer arithmetic. The `decimal-amounts` feature
+/// swaps in `rust_decimal::Decimal` with the same API. Either way the range
+/// is that of `Decimal`, and `checked_*` return `None` beyond it.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(Repr);
+
+#[cfg(not(feature = "decimal-amounts"))]
+type Repr = i128;
+
+#[cfg(feature = "decimal-amounts")]
+type Repr = Decimal;
+
+#[cfg(not(feature = "decimal-amounts"))]
+const UNIT: i128 = 10i128.pow(AMOUNT_SCALE);
+
+#[cfg(not(feature = "decimal-amounts"))]
+const MAX_UNITS: i128 = 79_228_162_514_264_337_593_543_950_335 * UNIT;
+
+/// Largest mantissa a `Decimal` can hold.
+#[cfg(not(feature = "decimal-amounts"))]
+const MAX_MANTISSA: u128 = (1 << 96) - 1;
+
+#[cfg(not(feature = "decimal-amounts"))]
+impl Amount {
+    pub const ZERO: Self = Self(0);
+
+    /// Rounds to four decimal places, half to even.
+    pub fn from_decimal(value: Decimal) -> Self {
+        let mut value =
+            value.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointNearestEven);
+        // Values with too many integer digits keep a smaller scale.
+        value.rescale(AMOUNT_SCALE);
+        Self(value.mantissa() * 10i128.pow(AMOUNT_SCALE - value.scale()))
+    }
+
+    /// The same value as a normalized `Decimal`. Amounts beyond about 7.9e24
+    /// lose their smallest digits, as `Decimal` arithmetic would.
+    pub fn to_decimal(self) -> Decimal {
+        let mut units = self.0;
+        let mut scale = AMOUNT_SCALE;
+        while units.unsigned_abs() > MAX_MANTISSA {
+            units = (units + 5 * units.signum()) / 10;
+            scale -= 1;
+        }
+        Decimal::from_i128_with_scale(units, scale).normalize()
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Self::bounded(self.0.checked_add(other.0)?)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        Self::bounded(self.0.checked_sub(other.0)?)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn from_minor_units(units: i64) -> Self {
+        Self(i128::from(units))
+    }
+
+    /// The amount in 1/10000 units, if that fits an `i64`.
+    pub fn to_minor_units(self) -> Option<i64> {
+        i64::try_from(self.0).ok()
+    }
+
+    fn bounded(units: i128) -> Option<Self> {
+        (units.abs() <= MAX_UNITS).then_some(Self(units))
+    }
+}
+
+#[cfg(feature = "decimal-amounts")]
+impl Amount {
+    pub const ZERO: Self = Self(Decimal::ZERO);
+
+    /// Rounds to four decimal places, half to even.
+    pub fn from_decimal(value: Decimal) -> Self {
+        Self(value.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointNearestEven))
+    }
+
+    pub fn to_decimal(self) -> Decimal {
+        self.0.normalize()
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn from_minor_units(units: i64) -> Self {
+        Self(Decimal::new(units, AMOUNT_SCALE))
+    }
+
+    /// The amount in 1/10000 units, if that fits an `i64`.
+    pub fn to_minor_units(self) -> Option<i64> {
+        let mut value = self.0;
+        value.rescale(AMOUNT_SCALE);
+        (value.scale() == AMOUNT_SCALE)
+            .then(|| i64::try_from(value.mantissa()).ok())
+            .flatten()
+    }
+}
+
+impl From<Decimal> for Amount {
+    fn from(value: Decimal) -> Self {
+        Self::from_decimal(value)
+    }
+}
+
+impl From<Amount> for Decimal {
+    fn from(value: Amount) -> Self {
+        value.to_decimal()
+    }
+}
+
+impl PartialEq<Decimal> for Amount {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.to_decimal() == *other
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_decimal().fmt(f)
+    }
+}
+
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Amount({self})")
+    }
+}
+
+impl Neg for Amount {
+    type Output = Self;
+
+    /// Never overflows: the range is symmetric around zero.
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn round_trips_four_decimals() {
+        for value in [
+            dec!(0),
+            dec!(1.5),
+            dec!(-2.0001),
+            dec!(1113.3196),
+            Decimal::MAX,
+        ] {
+            assert_eq!(Amount::from_decimal(value).to_decimal(), value.normalize());
+        }
+        assert_eq!(Amount::from_decimal(dec!(0.00005)), Amount::ZERO);
+        assert_eq!(Amount::from_decimal(dec!(0.00015)), dec!(0.0002));
+    }
+
+    #[test]
+    fn arithmetic_is_exact_and_checked() {
+        let a = Amount::from(dec!(0.1));
+        let b = Amount::from(dec!(0.2));
+        assert_eq!(a.checked_add(b).unwrap(), dec!(0.3));
+        assert_eq!(a.checked_sub(b).unwrap(), dec!(-0.1));
+        assert_eq!(-a, dec!(-0.1));
+        assert!(b > a);
+
+        let max = Amount::from(Decimal::MAX);
+        assert_eq!(max.checked_add(Amount::from(dec!(1))), None);
+        assert_eq!((-max).checked_sub(Amount::from(dec!(1))), None);
+        assert_eq!(
+            max.checked_sub(Amount::from(dec!(1))),
+            Some(Amount::from(Decimal::MAX - dec!(1)))
+        );
+    }
+
+    #[test]
+    fn minor_units_round_trip_within_i64() {
+        let amount = Amount::from(dec!(-1113.3196));
+        assert_eq!(amount.to_minor_units(), Some(-11_133_196));
+        assert_eq!(Amount::from_minor_units(-11_133_196), amount);
+        assert_eq!(Amount::from(Decimal::MAX).to_minor_units(), None);
+    }
+
+    #[test]
+    fn displays_normalized() {
+        assert_eq!(Amount::from(dec!(10.50)).to_string(), "10.5");
+        assert_eq!(format!("{:?}", Amount::from(dec!(3))), "Amount(3)");
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::error::PaymentError;
+use crate::events::{Balances, Event, EventKind, EventSubscriber};
+use crate::transaction::ClientId;
+
+/// `prev` of the first entry in a log.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub seq: u64,
+    pub accounts: usize,
+    pub state_hash: String,
+}
+
+/// Account balances a log starts from, written first by a run that starts
+/// from a saved state instead of empty accounts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Opening {
+    pub seq: u64,
+    pub accounts: Vec<OpeningAccount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OpeningAccount {
+    pub client: ClientId,
+    pub balances: Balances,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum AuditRecord {
+    Opening(Opening),
+    Event(Event),
+    Checkpoint(Checkpoint),
+}
+
+#[derive(Serialize)]
+struct EntryOut<'a> {
+    index: u64,
+    prev: &'a str,
+    hash: &'a str,
+    record: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct EntryIn {
+    index: u64,
+    prev: String,
+    hash: String,
+    record: Box<RawValue>,
+}
+
+/// Append-only, hash-chained audit log written as JSON lines.
+///
+/// Every event (accepted and rejected transactions alike) becomes one entry
+/// whose hash covers its index, the previous entry's hash and the exact record
+/// text, so editing, dropping or reordering any line breaks the chain from that
+/// point on. Every `checkpoint_every` transactions a checkpoint entry records a
+/// hash of all account balances as replayed from the events.
+///
+/// The hashes are not keyed, so whoever can write the file can also rewrite it
+/// from the edited entry onwards with fresh hashes and checkpoints. Detecting
+/// that needs a copy of `head()` kept where the writer cannot change it.
+pub struct AuditLog<W: Write> {
+    writer: W,
+    checkpoint_every: u64,
+    index: u64,
+    head: String,
+    state: BTreeMap<ClientId, Balances>,
+    last_seq: u64,
+    checkpoint_seq: u64,
+}
+
+impl<W: Write> AuditLog<W> {
+    /// A `checkpoint_every` of 0 only writes checkpoints on explicit calls.
+    pub fn new(writer: W, checkpoint_every: u64) -> Self {
+        Self {
+            writer,
+            checkpoint_every,
+            index: 0,
+            head: GENESIS.to_string(),
+            state: BTreeMap::new(),
+            last_seq: 0,
+            checkpoint_seq: 0,
+        }
+    }
+
+    pub fn head(&self) -> &str {
+        &self.head
+    }
+
+    /// Writes the balances the run starts from, after the event at `seq`.
+    /// Panics unless it is the first entry.
+    pub fn opening(
+        &mut self,
+        seq: u64,
+        accounts: impl IntoIterator<Item = (ClientId, Balances)>,
+    ) -> Result<(), PaymentError> {
+        assert_eq!(self.index, 0, "opening must be the first audit entry");
+        self.state = accounts.into_iter().collect();
+        let opening = Opening {
+            seq,
+            accounts: self
+                .state
+                .iter()
+                .map(|(&client, &balances)| OpeningAccount { client, balances })
+                .collect(),
+        };
+        self.append(&AuditRecord::Opening(opening))?;
+        self.last_seq = seq;
+        self.checkpoint_seq = seq;
+        Ok(())
+    }
+
+    pub fn append_event(&mut self, event: &Event) -> Result<(), PaymentError> {
+        if self.checkpoint_every > 0
+            && event.seq > self.last_seq
+            && self.last_seq >= self.checkpoint_seq + self.checkpoint_every
+        {
+            self.checkpoint()?;
+        }
+        self.append(&AuditRecord::Event(event.clone()))?;
+        track(&mut self.state, event);
+        self.last_seq = event.seq;
+        Ok(())
+    }
+
+    pub fn checkpoint(&mut self) -> Result<(), PaymentError> {
+        let checkpoint = Checkpoint {
+            seq: self.last_seq,
+            accounts: self.state.len(),
+            state_hash: state_hash(&self.state),
+        };
+        self.append(&AuditRecord::Checkpoint(checkpoint))?;
+        self.checkpoint_seq = self.last_seq;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PaymentError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn append(&mut self, record: &AuditRecord) -> Result<(), PaymentError> {
+        let record = serde_json::value::to_raw_value(record)?;
+        let hash = entry_hash(self.index, &self.head, record.get());
+        let entry = EntryOut {
+            index: self.index,
+            prev: &self.head,
+            hash: &hash,
+            record: &record,
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        self.index += 1;
+        self.head = hash;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> EventSubscriber for AuditLog<W> {
+    fn on_event(&mut self, event: &Event) {
+        if let Err(e) = self.append_event(event) {
+            error!(seq = event.seq, error = %e, "cannot append to audit log");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditSummary {
+    pub entries: u64,
+    pub checkpoints: u64,
+    pub head: String,
+}
+
+/// Recomputes the hash chain and every checkpoint and checks that each event
+/// starts from the balances its client's previous event left, or those of the
+/// opening entry, failing with
+/// `PaymentError::AuditChainBroken` at the first entry that does not verify.
+///
+/// A passing log is consistent, not authentic: compare `head` with a copy
+/// kept elsewhere to rule out a rewrite.
+pub fn verify<R: BufRead>(reader: R) -> Result<AuditSummary, PaymentError> {
+    let mut summary = AuditSummary {
+        entries: 0,
+        checkpoints: 0,
+        head: GENESIS.to_string(),
+    };
+    let mut state: BTreeMap<ClientId, Balances> = BTreeMap::new();
+    let mut last_seq = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let index = summary.entries;
+        let broken = |reason: String| PaymentError::AuditChainBroken(index, reason);
+
+        let entry: EntryIn =
+            serde_json::from_str(&line).map_err(|e| broken(format!("malformed entry: {e}")))?;
+        if entry.index != index {
+            return Err(broken(format!("found index {}", entry.index)));
+        }
+        if entry.prev != summary.head {
+            return Err(broken("previous hash does not match the chain".to_string()));
+        }
+        let hash = entry_hash(index, &entry.prev, entry.record.get());
+        if entry.hash != hash {
+            return Err(broken(format!("hash mismatch, recomputed {hash}")));
+        }
+
+        let record: AuditRecord = serde_json::from_str(entry.record.get())
+            .map_err(|e| broken(format!("malformed record: {e}")))?;
+        match record {
+            AuditRecord::Opening(opening) => {
+                if index != 0 {
+                    return Err(broken("opening balances after the first entry".to_string()));
+                }
+                state = opening
+                    .accounts
+                    .iter()
+                    .map(|account| (account.client, account.balances))
+                    .collect();
+                last_seq = opening.seq;
+            }
+            AuditRecord::Event(event) => {
+                if state.get(&event.client).copied().unwrap_or_default() != event.before {
+                    return Err(broken(format!(
+                        "event at seq {} does not start from the balances of client {}",
+                        event.seq, event.client
+                    )));
+                }
+                track(&mut state, &event);
+                last_seq = event.seq;
+            }
+            AuditRecord::Checkpoint(checkpoint) => {
+                if checkpoint.seq != last_seq || checkpoint.accounts != state.len() {
+                    return Err(broken(format!(
+                        "checkpoint claims seq {} and {} accounts, replayed seq {last_seq} and {} accounts",
+                        checkpoint.seq,
+                        checkpoint.accounts,
+                        state.len()
+                    )));
+                }
+                if checkpoint.state_hash != state_hash(&state) {
+                    return Err(broken(format!(
+                        "checkpoint at seq {} does not match the replayed events",
+                        checkpoint.seq
+                    )));
+                }
+                summary.checkpoints += 1;
+            }
+        }
+
+        summary.entries += 1;
+        summary.head = hash;
+    }
+
+    Ok(summary)
+}
+
+fn track(state: &mut BTreeMap<ClientId, Balances>, event: &Event) {
+    if !matches!(event.kind, EventKind::Rejected { .. }) {
+        state.insert(event.client, event.after);
+    }
+}
+
+fn entry_hash(index: u64, prev: &str, record: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{index}\n{prev}\n"));
+    hasher.update(record);
+    format!("{:x}", hasher.finalize())
+}
+
+fn state_hash(state: &BTreeMap<ClientId, Balances>) -> String {
+    let mut hasher = Sha256::new();
+    for (client, balances) in state {
+        hasher.update(format!(
+            "{client},{},{},{}\n",
+            balances.available.normalize(),
+            balances.held.normalize(),
+            balances.locked
+        ));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::transaction::{TransactionInput, TransactionType};
+    use rust_decimal::dec;
+    use std::sync::{Arc, Mutex};
+
+    fn audited_run(checkpoint_every: u64) -> String {
+        let audit = Arc::new(Mutex::new(AuditLog::new(Vec::new(), checkpoint_every)));
+        let subscriber = audit.clone();
+        let mut engine = PaymentEngine::builder()
+            .subscriber(move |event: &Event| subscriber.lock().unwrap().on_event(event))
+            .build();
+
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(10)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            2,
+            2,
+            Some(dec!(5)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            3,
+            Some(dec!(50)),
+        ));
+        let _ = engine.process(&TransactionInput::new(TransactionType::Dispute, 2, 2, None));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Chargeback,
+            2,
+            2,
+            None,
+        ));
+        drop(engine);
+
+        let mut audit = Arc::into_inner(audit).unwrap().into_inner().unwrap();
+        audit.checkpoint().unwrap();
+        String::from_utf8(audit.into_inner()).unwrap()
+    }
+
+    fn kinds(log: &str) -> Vec<String> {
+        log.lines()
+            .map(|line| {
+                let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                let record = &entry["record"];
+                match record["data"]["type"].as_str() {
+                    Some(kind) => kind.to_string(),
+                    None => record["kind"].as_str().unwrap().to_string(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn untouched_log_verifies() {
+        let log = audited_run(2);
+        let summary = verify(log.as_bytes()).unwrap();
+
+        assert_eq!(summary.entries, 9);
+        assert_eq!(summary.checkpoints, 3);
+        let last: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!(last["hash"], summary.head.as_str());
+        assert_eq!(
+            kinds(&log),
+            [
+                "deposited",
+                "deposited",
+                "checkpoint",
+                "rejected",
+                "dispute_opened",
+                "checkpoint",
+                "charged_back",
+                "account_locked",
+                "checkpoint",
+            ]
+        );
+    }
+
+    #[test]
+    fn log_of_a_run_from_saved_state_verifies() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+
+        let mut audit = AuditLog::new(Vec::new(), 0);
+        let accounts = engine.accounts().map(|a| (a.client, Balances::of(a)));
+        audit.opening(1, accounts).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.subscribe(sender);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(dec!(4)),
+            ))
+            .unwrap();
+        for event in receiver.try_iter() {
+            audit.append_event(&event).unwrap();
+        }
+        audit.checkpoint().unwrap();
+        let log = String::from_utf8(audit.into_inner()).unwrap();
+
+        let summary = verify(log.as_bytes()).unwrap();
+        assert_eq!((summary.entries, summary.checkpoints), (3, 1));
+        assert_eq!(kinds(&log), ["opening", "withdrawn", "checkpoint"]);
+
+        let without_opening: Vec<&str> = log.lines().skip(1).collect();
+        assert!(verify(without_opening.join("\n").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn edited_entry_is_pinpointed() {
+        let log = audited_run(0);
+        let tampered: Vec<String> = log
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                if i == 1 {
+                    line.replace("\"amount\":\"5\"", "\"amount\":\"500\"")
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+        assert_ne!(tampered.join("\n"), log.trim_end());
+
+        let err = verify(tampered.join("\n").as_bytes()).err().unwrap();
+        assert!(matches!(err, PaymentError::AuditChainBroken(1, _)), "{err}");
+    }
+
+    #[test]
+    fn entries_carry_and_protect_provenance() {
+        let audit = Arc::new(Mutex::new(AuditLog::new(Vec::new(), 0)));
+        let subscriber = audit.clone();
+        let engine = PaymentEngine::builder()
+            .subscriber(move |event: &Event| subscriber.lock().unwrap().on_event(event))
+            .build();
+        let csv_data = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,50.0\n";
+        drop(crate::io::process_csv_named(csv_data.as_bytes(), "in.csv", engine).unwrap());
+        let log = String::from_utf8(
+            Arc::into_inner(audit)
+                .unwrap()
+                .into_inner()
+                .unwrap()
+                .into_inner(),
+        )
+        .unwrap();
+
+        let rejected: serde_json::Value =
+            serde_json::from_str(log.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(
+            rejected["record"]["data"]["provenance"],
+            serde_json::json!({ "file": "in.csv", "line": 3, "byte": 39 })
+        );
+        verify(log.as_bytes()).unwrap();
+        let moved = log.replace("\"line\":3", "\"line\":4");
+        let err = verify(moved.as_bytes()).err().unwrap();
+        assert!(matches!(err, PaymentError::AuditChainBroken(1, _)), "{err}");
+    }
+
+    #[test]
+    fn dropped_entry_is_pinpointed() {
+        let log = audited_run(0);
+        let lines: Vec<&str> = log
+            .lines()
+            .filter(|line| !line.contains("rejected"))
+            .collect();
+
+        let err = verify(lines.join("\n").as_bytes()).err().unwrap();
+        assert!(matches!(err, PaymentError::AuditChainBroken(2, _)), "{err}");
+    }
+
+    fn records(log: &str) -> Vec<AuditRecord> {
+        log.lines()
+            .map(|line| {
+                let entry: EntryIn = serde_json::from_str(line).unwrap();
+                serde_json::from_str(entry.record.get()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rehashed_edit_breaks_balance_continuity() {
+        let log = audited_run(0);
+        let mut forged = AuditLog::new(Vec::new(), 0);
+        for record in records(&log) {
+            match record {
+                AuditRecord::Event(mut event) => {
+                    if event.seq == 1 {
+                        event.after.available = dec!(1000);
+                    }
+                    forged.append(&AuditRecord::Event(event)).unwrap();
+                }
+                record => forged.append(&record).unwrap(),
+            }
+        }
+        let forged = String::from_utf8(forged.into_inner()).unwrap();
+
+        let err = verify(forged.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "audit log broken at entry 2: event at seq 3 does not start from the balances of client 1"
+        );
+    }
+
+    #[test]
+    fn rehashed_checkpoint_must_match_replay() {
+        let log = audited_run(0);
+        let mut forged = AuditLog::new(Vec::new(), 0);
+        for record in records(&log) {
+            match record {
+                AuditRecord::Checkpoint(checkpoint) => {
+                    let checkpoint = Checkpoint {
+                        accounts: 3,
+                        ..checkpoint
+                    };
+                    forged.append(&AuditRecord::Checkpoint(checkpoint)).unwrap();
+                }
+                record => forged.append(&record).unwrap(),
+            }
+        }
+        let forged = String::from_utf8(forged.into_inner()).unwrap();
+
+        let err = verify(forged.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "audit log broken at entry 6: checkpoint claims seq 5 and 3 accounts, replayed seq 5 and 2 accounts"
+        );
+    }
+
+    #[test]
+    fn consistent_rewrite_is_only_caught_by_the_head() {
+        let log = audited_run(0);
+        let head = verify(log.as_bytes()).unwrap().head;
+        let mut forged = AuditLog::new(Vec::new(), 0);
+        for record in records(&log) {
+            if let AuditRecord::Event(mut event) = record {
+                if event.client == ClientId(1) {
+                    if let EventKind::Deposited { amount } = &mut event.kind {
+                        *amount = dec!(1000);
+                    } else {
+                        event.before.available = dec!(1000);
+                    }
+                    event.after.available = dec!(1000);
+                }
+                forged.append_event(&event).unwrap();
+            }
+        }
+        forged.checkpoint().unwrap();
+        let forged = String::from_utf8(forged.into_inner()).unwrap();
+
+        let summary = verify(forged.as_bytes()).unwrap();
+        assert_ne!(summary.head, head);
+    }
+}
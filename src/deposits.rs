@@ -0,0 +1,556 @@
+//! Deposits kept for dispute lookups.
+//!
+//! Transaction ids are usually close to sequential, so most deposits live in
+//! a dense range indexed by `id - base`: a 16-byte slot per id plus one bit
+//! each for "present" and "disputed". Ids far outside that range, and amounts
+//! that do not fit `i64` minor units, go to a hash map instead. Refunds are
+//! rare and are kept in their own map.
+//!
+//! With a dispute horizon, deposits made more than that many transactions ago
+//! are evicted and can no longer be disputed or refunded. Deposits under
+//! dispute are kept until the dispute is settled. Evicted ids are remembered
+//! in a bitset keyed by `id / 64`, one bit each, so they still count as
+//! duplicates.
+//!
+//! Between `begin` and `commit` every change is journaled so `rollback` can
+//! restore the index exactly. Eviction is skipped while a journal is open.
+
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+
+use crate::amount::Amount;
+use crate::error::PaymentError;
+use crate::transaction::{ClientId, TxId};
+
+/// Ids per bitset word. The dense range grows and is evicted in whole blocks.
+const BLOCK: usize = 64;
+
+/// How far past the end of the dense range an id may land and still extend
+/// it. Every skipped id costs an empty slot.
+const MAX_GAP: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredDeposit {
+    client: ClientId,
+    amount: Amount,
+    refunded: Amount,
+    disputed: bool,
+}
+
+impl StoredDeposit {
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn refunded(&self) -> Amount {
+        self.refunded
+    }
+
+    pub fn disputed(&self) -> bool {
+        self.disputed
+    }
+
+    /// `None` only for refunds replayed from an event log that overflow.
+    pub fn outstanding(&self) -> Option<Amount> {
+        self.amount.checked_sub(self.refunded)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    client: ClientId,
+    units: i64,
+}
+
+const EMPTY: Slot = Slot {
+    client: ClientId(0),
+    units: 0,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SparseDeposit {
+    client: ClientId,
+    amount: Amount,
+    disputed: bool,
+}
+
+/// Inverse of one change made while journaling.
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    /// `block_seq` is the previous newest-deposit stamp of a dense slot's
+    /// block, `None` for a sparse deposit.
+    Inserted {
+        id: TxId,
+        block_seq: Option<u64>,
+    },
+    Disputed(TxId, bool),
+    Refunded(TxId, Option<Amount>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Journal {
+    base: u64,
+    slots: usize,
+    sparse_order: usize,
+    len: usize,
+    changes: Vec<Change>,
+}
+
+/// Approximate memory held by a [`DepositIndex`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepositMemory {
+    /// Slots in the dense range, including gaps.
+    pub dense_slots: usize,
+    pub sparse: usize,
+    pub refunds: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DepositIndex {
+    horizon: Option<u64>,
+    base: u64,
+    slots: VecDeque<Slot>,
+    present: VecDeque<u64>,
+    disputed: VecDeque<u64>,
+    /// Sequence number of the newest deposit in each block.
+    block_seq: VecDeque<u64>,
+    sparse: HashMap<TxId, SparseDeposit>,
+    /// Insertion order of sparse deposits, only kept with a horizon.
+    sparse_order: VecDeque<(u64, TxId)>,
+    refunded: HashMap<TxId, Amount>,
+    /// Bits of evicted ids, keyed by `id / BLOCK`.
+    evicted_ids: HashMap<u64, u64>,
+    len: usize,
+    evicted: u64,
+    journal: Option<Journal>,
+}
+
+impl DepositIndex {
+    /// `horizon` is the number of transactions after which an undisputed
+    /// deposit is evicted, or `None` to keep every deposit.
+    pub fn new(horizon: Option<u64>) -> Self {
+        Self {
+            horizon,
+            ..Self::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Deposits dropped because they passed the dispute horizon.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn contains(&self, id: TxId) -> bool {
+        self.slot(id).is_some() || self.sparse.contains_key(&id)
+    }
+
+    /// Whether `id` was a deposit that passed the dispute horizon.
+    pub fn was_evicted(&self, id: TxId) -> bool {
+        let (word, mask) = evicted_bit(id);
+        self.evicted_ids
+            .get(&word)
+            .is_some_and(|bits| bits & mask != 0)
+    }
+
+    pub fn get(&self, id: TxId) -> Option<StoredDeposit> {
+        let refunded = self.refunded.get(&id).copied().unwrap_or_default();
+        if let Some(i) = self.slot(id) {
+            let slot = self.slots[i];
+            return Some(StoredDeposit {
+                client: slot.client,
+                amount: Amount::from_minor_units(slot.units),
+                refunded,
+                disputed: bit(&self.disputed, i),
+            });
+        }
+        self.sparse.get(&id).map(|deposit| StoredDeposit {
+            client: deposit.client,
+            amount: deposit.amount,
+            refunded,
+            disputed: deposit.disputed,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TxId, StoredDeposit)> + '_ {
+        let dense = (0..self.slots.len())
+            .filter(|&i| bit(&self.present, i))
+            .map(|i| TxId(self.base + i as u64));
+        dense
+            .chain(self.sparse.keys().copied())
+            .map(|id| (id, self.get(id).expect("listed deposit exists")))
+    }
+
+    /// Stores a new deposit made at sequence number `seq`. The caller rejects
+    /// duplicate ids, evicted ones included, first.
+    pub fn insert(&mut self, id: TxId, client: ClientId, amount: Amount, seq: u64) {
+        debug_assert!(
+            !self.contains(id) && !self.was_evicted(id),
+            "duplicate deposit {id}"
+        );
+        self.len += 1;
+        if let Some(units) = amount.to_minor_units()
+            && let Some(i) = self.dense_index(id)
+        {
+            let block_seq = std::mem::replace(&mut self.block_seq[i / BLOCK], seq);
+            self.slots[i] = Slot { client, units };
+            set_bit(&mut self.present, i, true);
+            self.journal(Change::Inserted {
+                id,
+                block_seq: Some(block_seq),
+            });
+            return;
+        }
+        self.journal(Change::Inserted {
+            id,
+            block_seq: None,
+        });
+        self.sparse.insert(
+            id,
+            SparseDeposit {
+                client,
+                amount,
+                disputed: false,
+            },
+        );
+        if self.horizon.is_some() {
+            self.sparse_order.push_back((seq, id));
+        }
+    }
+
+    /// Does nothing if the deposit is not stored.
+    pub fn set_disputed(&mut self, id: TxId, disputed: bool) {
+        let previous = if let Some(i) = self.slot(id) {
+            let previous = bit(&self.disputed, i);
+            set_bit(&mut self.disputed, i, disputed);
+            previous
+        } else if let Some(deposit) = self.sparse.get_mut(&id) {
+            std::mem::replace(&mut deposit.disputed, disputed)
+        } else {
+            return;
+        };
+        self.journal(Change::Disputed(id, previous));
+    }
+
+    /// Does nothing if the deposit is not stored. Fails, leaving the
+    /// deposit unchanged, if its refunded total would overflow.
+    pub fn add_refund(&mut self, id: TxId, amount: Amount) -> Result<(), PaymentError> {
+        if self.contains(id) {
+            let previous = self.refunded.get(&id).copied();
+            let refunded = previous
+                .unwrap_or_default()
+                .checked_add(amount)
+                .ok_or(PaymentError::Overflow(id))?;
+            self.refunded.insert(id, refunded);
+            self.journal(Change::Refunded(id, previous));
+        }
+        Ok(())
+    }
+
+    /// Starts journaling changes. Panics if a journal is already open.
+    pub fn begin(&mut self) {
+        assert!(self.journal.is_none(), "deposit journal already open");
+        self.journal = Some(Journal {
+            base: self.base,
+            slots: self.slots.len(),
+            sparse_order: self.sparse_order.len(),
+            len: self.len,
+            changes: Vec::new(),
+        });
+    }
+
+    /// Keeps every change since `begin`.
+    pub fn commit(&mut self) {
+        self.journal = None;
+    }
+
+    /// Undoes every change since `begin`.
+    pub fn rollback(&mut self) {
+        let Some(journal) = self.journal.take() else {
+            return;
+        };
+        for change in journal.changes.into_iter().rev() {
+            match change {
+                Change::Inserted {
+                    id,
+                    block_seq: Some(block_seq),
+                } => {
+                    let i = self.slot(id).expect("journaled deposit is stored");
+                    self.slots[i] = EMPTY;
+                    set_bit(&mut self.present, i, false);
+                    set_bit(&mut self.disputed, i, false);
+                    self.block_seq[i / BLOCK] = block_seq;
+                }
+                Change::Inserted {
+                    id,
+                    block_seq: None,
+                } => {
+                    self.sparse.remove(&id);
+                }
+                Change::Disputed(id, disputed) => self.set_disputed(id, disputed),
+                Change::Refunded(id, Some(amount)) => {
+                    self.refunded.insert(id, amount);
+                }
+                Change::Refunded(id, None) => {
+                    self.refunded.remove(&id);
+                }
+            }
+        }
+        self.slots.truncate(journal.slots);
+        self.present.truncate(journal.slots / BLOCK);
+        self.disputed.truncate(journal.slots / BLOCK);
+        self.block_seq.truncate(journal.slots / BLOCK);
+        self.sparse_order.truncate(journal.sparse_order);
+        self.base = journal.base;
+        self.len = journal.len;
+    }
+
+    /// Evicts undisputed deposits made more than the horizon before `seq`.
+    pub fn expire(&mut self, seq: u64) {
+        if self.journal.is_some() {
+            return;
+        }
+        let Some(cutoff) = self.horizon.and_then(|horizon| seq.checked_sub(horizon)) else {
+            return;
+        };
+
+        while self
+            .block_seq
+            .front()
+            .is_some_and(|&newest| newest < cutoff)
+        {
+            for i in 0..BLOCK {
+                if !bit(&self.present, i) {
+                    continue;
+                }
+                let id = TxId(self.base + i as u64);
+                if bit(&self.disputed, i) {
+                    let slot = self.slots[i];
+                    self.sparse.insert(
+                        id,
+                        SparseDeposit {
+                            client: slot.client,
+                            amount: Amount::from_minor_units(slot.units),
+                            disputed: true,
+                        },
+                    );
+                    self.sparse_order.push_back((seq, id));
+                } else {
+                    self.evict(id);
+                }
+            }
+            self.slots.drain(..BLOCK);
+            self.present.pop_front();
+            self.disputed.pop_front();
+            self.block_seq.pop_front();
+            self.base += BLOCK as u64;
+        }
+
+        while let Some(&(inserted, id)) = self.sparse_order.front()
+            && inserted < cutoff
+        {
+            self.sparse_order.pop_front();
+            if self.sparse[&id].disputed {
+                self.sparse_order.push_back((seq, id));
+            } else {
+                self.sparse.remove(&id);
+                self.evict(id);
+            }
+        }
+    }
+
+    pub fn memory(&self) -> DepositMemory {
+        let words = self.present.capacity() + self.disputed.capacity() + self.block_seq.capacity();
+        DepositMemory {
+            dense_slots: self.slots.len(),
+            sparse: self.sparse.len(),
+            refunds: self.refunded.len(),
+            bytes: self.slots.capacity() * size_of::<Slot>()
+                + words * size_of::<u64>()
+                + hash_map_bytes(&self.sparse)
+                + self.sparse_order.capacity() * size_of::<(u64, TxId)>()
+                + hash_map_bytes(&self.refunded)
+                + hash_map_bytes(&self.evicted_ids),
+        }
+    }
+
+    fn journal(&mut self, change: Change) {
+        if let Some(journal) = &mut self.journal {
+            journal.changes.push(change);
+        }
+    }
+
+    fn evict(&mut self, id: TxId) {
+        let (word, mask) = evicted_bit(id);
+        *self.evicted_ids.entry(word).or_default() |= mask;
+        self.refunded.remove(&id);
+        self.len -= 1;
+        self.evicted += 1;
+    }
+
+    fn slot(&self, id: TxId) -> Option<usize> {
+        let offset = id.0.checked_sub(self.base)?;
+        let i = usize::try_from(offset).ok()?;
+        (i < self.slots.len() && bit(&self.present, i)).then_some(i)
+    }
+
+    /// Position of `id` in the dense range, growing the range when `id` is
+    /// near its end.
+    fn dense_index(&mut self, id: TxId) -> Option<usize> {
+        if self.slots.is_empty() {
+            self.base = id.0 - id.0 % BLOCK as u64;
+        }
+        let offset = id.0.checked_sub(self.base)?;
+        if offset >= self.slots.len() as u64 + MAX_GAP {
+            return None;
+        }
+        let i = offset as usize;
+        while self.slots.len() <= i {
+            self.slots.extend([EMPTY; BLOCK]);
+            self.present.push_back(0);
+            self.disputed.push_back(0);
+            self.block_seq.push_back(0);
+        }
+        Some(i)
+    }
+}
+
+/// Rough heap size of a `HashMap`: its entries plus one control byte each.
+pub fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+fn evicted_bit(id: TxId) -> (u64, u64) {
+    (id.0 / BLOCK as u64, 1 << (id.0 % BLOCK as u64))
+}
+
+fn bit(words: &VecDeque<u64>, i: usize) -> bool {
+    words[i / BLOCK] & (1 << (i % BLOCK)) != 0
+}
+
+fn set_bit(words: &mut VecDeque<u64>, i: usize, value: bool) {
+    let mask = 1 << (i % BLOCK);
+    if value {
+        words[i / BLOCK] |= mask;
+    } else {
+        words[i / BLOCK] &= !mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn amount(value: rust_decimal::Decimal) -> Amount {
+        Amount::from(value)
+    }
+
+    #[test]
+    fn sequential_ids_use_dense_slots() {
+        let mut index = DepositIndex::new(None);
+        for id in 1..=1000 {
+            index.insert(TxId(id), ClientId(id % 7), amount(dec!(1.5)), id);
+        }
+        index.set_disputed(TxId(500), true);
+        index.add_refund(TxId(10), amount(dec!(0.5))).unwrap();
+
+        let memory = index.memory();
+        assert_eq!(memory.sparse, 0);
+        assert_eq!(memory.dense_slots, 1024);
+        assert_eq!(index.len(), 1000);
+
+        let deposit = index.get(TxId(500)).unwrap();
+        assert_eq!(deposit.client(), ClientId(500 % 7));
+        assert!(deposit.disputed());
+        assert_eq!(index.get(TxId(10)).unwrap().outstanding().unwrap(), dec!(1));
+        assert!(!index.contains(TxId(0)));
+        assert!(!index.contains(TxId(1001)));
+    }
+
+    #[test]
+    fn scattered_ids_and_large_amounts_go_sparse() {
+        let mut index = DepositIndex::new(None);
+        index.insert(TxId(5), ClientId(1), amount(dec!(1)), 1);
+        index.insert(TxId(1_000_000), ClientId(1), amount(dec!(2)), 2);
+        index.insert(TxId(2), ClientId(1), amount(rust_decimal::Decimal::MAX), 3);
+        index.insert(TxId(3), ClientId(2), amount(dec!(3)), 4);
+
+        assert_eq!(index.memory().sparse, 2);
+        assert_eq!(
+            index.get(TxId(2)).unwrap().amount(),
+            rust_decimal::Decimal::MAX
+        );
+        let mut ids: Vec<u64> = index.iter().map(|(id, _)| id.0).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [2, 3, 5, 1_000_000]);
+    }
+
+    #[test]
+    fn horizon_evicts_old_undisputed_deposits() {
+        let mut index = DepositIndex::new(Some(100));
+        for id in 0..200 {
+            index.insert(TxId(id), ClientId(1), amount(dec!(1)), id);
+        }
+        index.insert(TxId(u64::MAX), ClientId(1), amount(dec!(1)), 10);
+        index.set_disputed(TxId(3), true);
+        index.add_refund(TxId(4), amount(dec!(0.5))).unwrap();
+
+        index.expire(250);
+        // Blocks whose newest deposit is older than seq 150 are gone, except the disputed one.
+        assert!(index.contains(TxId(3)));
+        assert!(!index.contains(TxId(4)));
+        assert!(!index.contains(TxId(127)));
+        assert!(index.contains(TxId(128)));
+        assert!(!index.contains(TxId(u64::MAX)));
+        assert!(index.was_evicted(TxId(4)) && index.was_evicted(TxId(u64::MAX)));
+        assert!(!index.was_evicted(TxId(3)) && !index.was_evicted(TxId(128)));
+        assert_eq!(index.evicted(), 128);
+        assert_eq!(index.memory().refunds, 0);
+
+        index.set_disputed(TxId(3), false);
+        index.expire(400);
+        assert!(index.is_empty());
+        assert_eq!(index.evicted(), 201);
+    }
+
+    #[test]
+    fn rollback_restores_journaled_changes() {
+        let mut index = DepositIndex::new(Some(100));
+        for id in 0..10 {
+            index.insert(TxId(id), ClientId(1), amount(dec!(1)), id);
+        }
+        index.set_disputed(TxId(3), true);
+        index.add_refund(TxId(4), amount(dec!(0.5))).unwrap();
+        let before = index.clone();
+
+        index.begin();
+        index.insert(TxId(200), ClientId(2), amount(dec!(2)), 10);
+        index.insert(TxId(5_000), ClientId(2), amount(dec!(3)), 11);
+        index.set_disputed(TxId(3), false);
+        index.set_disputed(TxId(5), true);
+        index.add_refund(TxId(4), amount(dec!(0.25))).unwrap();
+        index.add_refund(TxId(6), amount(dec!(1))).unwrap();
+        index.expire(500);
+        assert_eq!(index.len(), 12);
+        index.rollback();
+        assert_eq!(index, before);
+
+        index.begin();
+        index.insert(TxId(10), ClientId(1), amount(dec!(1)), 10);
+        index.commit();
+        assert!(index.get(TxId(10)).is_some());
+    }
+}
@@ -1,23 +1,360 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Instant;
 
-use rust_decimal::Decimal;
 use rust_decimal::dec;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
 
 use crate::account::Account;
-use crate::error::PaymentError;
-use crate::transaction::{TransactionRecord, TransactionType};
-
-#[derive(Debug, Clone)]
-struct StoredDeposit {
-    client: u16,
-    amount: Decimal,
-    disputed: bool,
+use crate::amount::Amount;
+use crate::deposits::{DepositIndex, DepositMemory, hash_map_bytes};
+use crate::error::{InvariantViolation, PaymentError};
+use crate::events::{Balances, Event, EventKind, EventSubscriber};
+use crate::hooks::{HookContext, TransactionHook};
+use crate::metrics::Metrics;
+use crate::risk::{ClientRisk, RiskAction, RiskConfig, RiskEngine, RiskHit};
+use crate::transaction::{
+    ClientId, Provenance, TransactionInput, TransactionRecord, TransactionType, TxId,
+};
+
+/// Number of decimal places the engine keeps for monetary amounts.
+pub const AMOUNT_SCALE: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+struct StoredAuthorization {
+    client: ClientId,
+    amount: Amount,
+    remaining: Amount,
+    expires_at: Option<u64>,
+}
+
+/// When an uncaptured authorization hold is released automatically.
+///
+/// `AfterTransactions(n)` keeps the hold open for the next `n` processed
+/// transactions. `AfterSeconds(s)` is measured against the `timestamp` column,
+/// so holds never expire on input that carries no timestamps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldExpiry {
+    #[default]
+    Never,
+    AfterTransactions(u64),
+    AfterSeconds(u64),
+}
+
+/// What to do with input amounts that have more than [`AMOUNT_SCALE`] decimal places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrecisionPolicy {
+    /// The default. Earlier versions accepted these amounts and truncated
+    /// only on output; `Truncate` is the closest match.
+    #[default]
+    Reject,
+    #[serde(rename = "round", alias = "round-half-even")]
+    RoundHalfEven,
+    Truncate,
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "round" | "round-half-even" => Ok(Self::RoundHalfEven),
+            "truncate" => Ok(Self::Truncate),
+            other => Err(format!(
+                "unknown precision policy `{other}` (expected reject, round or truncate)"
+            )),
+        }
+    }
+}
+
+/// What to do with a deposit or authorization whose id is already stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Every reused id is rejected.
+    #[default]
+    Reject,
+    /// A resubmission with the same type, client and amount is accepted
+    /// without effect; anything else reusing the id is still rejected.
+    Idempotent,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "idempotent" => Ok(Self::Idempotent),
+            other => Err(format!(
+                "unknown duplicate policy `{other}` (expected reject or idempotent)"
+            )),
+        }
+    }
+}
+
+/// Engine settings, built in code or loaded from TOML with [`EngineConfig::from_toml`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub hold_expiry: HoldExpiry,
+    pub max_amount: Option<Decimal>,
+    pub precision: PrecisionPolicy,
+    pub risk: RiskConfig,
+    /// Evict undisputed deposits after this many transactions, see `deposits`.
+    pub dispute_horizon: Option<u64>,
+    /// Transaction types the engine accepts; others fail with `TypeDisabled`.
+    pub enabled_types: HashSet<TransactionType>,
+    pub duplicates: DuplicatePolicy,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            hold_expiry: HoldExpiry::default(),
+            max_amount: None,
+            precision: PrecisionPolicy::default(),
+            risk: RiskConfig::default(),
+            dispute_horizon: None,
+            enabled_types: TransactionType::ALL.into(),
+            duplicates: DuplicatePolicy::default(),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn from_toml(s: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(s)
+    }
+}
+
+/// How `PaymentEngine::process_batch` handles a failing record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Atomicity {
+    /// The first failure rolls back every record of the batch.
+    Atomic,
+    /// Each record stands alone, as with `process`.
+    #[default]
+    BestEffort,
+}
+
+/// How the transactions run through a [`Fork`] change one client's account.
+/// `available`, `held` and `total` are differences; `locked` is the state
+/// the account ends up in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AccountDelta {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpiredHold {
+    pub authorization: TxId,
+    pub client: ClientId,
+    pub amount: Amount,
+}
+
+/// Approximate heap bytes held by the engine's main tables. Hooks,
+/// subscribers, risk and invariant bookkeeping are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub accounts: usize,
+    pub deposits: DepositMemory,
+    pub authorizations: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.accounts + self.deposits.bytes + self.authorizations
+    }
 }
 
 #[derive(Default)]
 pub struct PaymentEngine {
-    accounts: HashMap<u16, Account>,
-    deposits: HashMap<u32, StoredDeposit>,
+    config: EngineConfig,
+    accounts: HashMap<ClientId, Account>,
+    deposits: DepositIndex,
+    authorizations: HashMap<TxId, StoredAuthorization>,
+    expiry_queue: BTreeSet<(u64, TxId)>,
+    expired: Vec<ExpiredHold>,
+    seq: u64,
+    clock: Option<u64>,
+    subscribers: Vec<Box<dyn EventSubscriber>>,
+    hooks: Vec<Box<dyn TransactionHook>>,
+    risk: RiskEngine,
+    metrics: Option<Metrics>,
+    ledger: Ledger,
+    journal: Option<Journal>,
+}
+
+/// Undo log of an open batch: engine state from before the batch for every
+/// client and authorization it touched. Events are held back until commit.
+struct Journal {
+    seq: u64,
+    clock: Option<u64>,
+    expired: usize,
+    risk_hits: usize,
+    net_flow: Decimal,
+    balance_total: Decimal,
+    overflow: bool,
+    clients: HashMap<ClientId, ClientSnapshot>,
+    authorizations: HashMap<TxId, AuthorizationSnapshot>,
+    events: Vec<Event>,
+}
+
+struct ClientSnapshot {
+    account: Option<Account>,
+    open_holds: Option<Decimal>,
+    locked_at: Option<Balances>,
+    risk: ClientRisk,
+}
+
+struct AuthorizationSnapshot {
+    authorization: Option<StoredAuthorization>,
+    queued: bool,
+}
+
+/// Sums of amounts with `AMOUNT_SCALE` decimal places are exact below this
+/// magnitude; beyond it `Decimal` starts rounding and conservation is not checked.
+const EXACT_LIMIT: Decimal = dec!(1_000_000_000_000_000_000_000_000);
+
+/// Bookkeeping derived only from emitted events, used by the invariant checks.
+#[derive(Default)]
+struct Ledger {
+    open_holds: HashMap<ClientId, Decimal>,
+    locked_at: HashMap<ClientId, Balances>,
+    net_flow: Decimal,
+    balance_total: Decimal,
+    overflow: bool,
+}
+
+impl Ledger {
+    fn record(&mut self, client: ClientId, kind: &EventKind, before: Balances, after: Balances) {
+        let zero = dec!(0);
+        let (flow, held) = match *kind {
+            EventKind::Deposited { amount } => (amount, zero),
+            EventKind::Withdrawn { amount } | EventKind::Refunded { amount } => (-amount, zero),
+            EventKind::ChargedBack { amount } | EventKind::Captured { amount } => {
+                (-amount, -amount)
+            }
+            EventKind::DisputeOpened { amount } | EventKind::Authorized { amount, .. } => {
+                (zero, amount)
+            }
+            EventKind::DisputeResolved { amount }
+            | EventKind::Voided { amount }
+            | EventKind::HoldExpired { amount } => (zero, -amount),
+            EventKind::AccountLocked => {
+                self.locked_at.insert(client, after);
+                return;
+            }
+            EventKind::AccountOpened { .. } | EventKind::AccountClosed { .. } => return,
+            EventKind::Rejected { .. } | EventKind::Replayed { .. } => return,
+        };
+
+        let open = self.open_holds.entry(client).or_default();
+        *open = open.saturating_add(held);
+
+        let total = |b: Balances| b.available.checked_add(b.held);
+        let delta = total(after)
+            .zip(total(before))
+            .and_then(|(a, b)| a.checked_sub(b));
+        let net_flow = self.net_flow.checked_add(flow);
+        let balance_total = delta.and_then(|delta| self.balance_total.checked_add(delta));
+        match net_flow.zip(balance_total) {
+            Some((net_flow, balance_total))
+                if net_flow.abs() < EXACT_LIMIT && balance_total.abs() < EXACT_LIMIT =>
+            {
+                self.net_flow = net_flow;
+                self.balance_total = balance_total;
+            }
+            _ => self.overflow = true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PaymentEngineBuilder {
+    config: EngineConfig,
+    metrics: bool,
+    subscribers: Vec<Box<dyn EventSubscriber>>,
+    hooks: Vec<Box<dyn TransactionHook>>,
+}
+
+impl PaymentEngineBuilder {
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn hold_expiry(mut self, hold_expiry: HoldExpiry) -> Self {
+        self.config.hold_expiry = hold_expiry;
+        self
+    }
+
+    pub fn max_amount(mut self, max_amount: Decimal) -> Self {
+        self.config.max_amount = Some(max_amount);
+        self
+    }
+
+    pub fn precision(mut self, precision: PrecisionPolicy) -> Self {
+        self.config.precision = precision;
+        self
+    }
+
+    pub fn risk(mut self, risk: RiskConfig) -> Self {
+        self.config.risk = risk;
+        self
+    }
+
+    pub fn dispute_horizon(mut self, transactions: u64) -> Self {
+        self.config.dispute_horizon = Some(transactions);
+        self
+    }
+
+    pub fn enabled_types(mut self, types: impl IntoIterator<Item = TransactionType>) -> Self {
+        self.config.enabled_types = types.into_iter().collect();
+        self
+    }
+
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.config.duplicates = duplicates;
+        self
+    }
+
+    /// Collect per-transaction counters and latency, see `metrics::render`.
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+
+    pub fn hook(mut self, hook: impl TransactionHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn subscriber(mut self, subscriber: impl EventSubscriber + 'static) -> Self {
+        self.subscribers.push(Box::new(subscriber));
+        self
+    }
+
+    pub fn build(self) -> PaymentEngine {
+        PaymentEngine {
+            risk: RiskEngine::new(self.config.risk.clone()),
+            deposits: DepositIndex::new(self.config.dispute_horizon),
+            config: self.config,
+            subscribers: self.subscribers,
+            hooks: self.hooks,
+            metrics: self.metrics.then(Metrics::default),
+            ..PaymentEngine::default()
+        }
+    }
 }
 
 impl PaymentEngine {
@@ -25,432 +362,2512 @@ impl PaymentEngine {
         Self::default()
     }
 
-    pub fn process(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
-        if let Some(account) = self.accounts.get(&tx.client)
-            && account.locked
+    pub fn builder() -> PaymentEngineBuilder {
+        PaymentEngineBuilder::default()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self::builder().config(config).build()
+    }
+
+    /// Rebuilds engine state from an event log produced by a subscriber.
+    pub fn from_events<'a>(
+        events: impl IntoIterator<Item = &'a Event>,
+    ) -> Result<Self, PaymentError> {
+        let mut engine = Self::new();
+        for event in events {
+            engine.apply_event(event)?;
+        }
+        Ok(engine)
+    }
+
+    pub fn subscribe(&mut self, subscriber: impl EventSubscriber + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    pub fn process(&mut self, tx: &TransactionInput) -> Result<(), PaymentError> {
+        let started = self.metrics.is_some().then(Instant::now);
+        let result = self.process_record(tx);
+        if let (Some(metrics), Some(started)) = (&mut self.metrics, started) {
+            metrics.record(tx.tx_type, result.as_ref().copied(), started.elapsed());
+        }
+        #[cfg(debug_assertions)]
+        if let Err(violation) = self.check_transaction_invariants(tx.client) {
+            panic!(
+                "invariant violated after transaction {}: {violation}",
+                tx.tx
+            );
+        }
+        match &tx.provenance {
+            Some(provenance) => {
+                result.map_err(|e| PaymentError::At(provenance.clone(), Box::new(e)))
+            }
+            None => result,
+        }
+    }
+
+    /// Cross-checks every account against the stored deposits and
+    /// authorizations, the balances it had when it was locked, and the net
+    /// money that has entered the system. This walks all state; debug builds
+    /// run the per-account subset after every transaction instead.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let mut expected_held: HashMap<ClientId, Amount> = HashMap::new();
+        let open_disputes = self
+            .deposits
+            .iter()
+            .filter(|(_, deposit)| deposit.disputed())
+            .map(|(_, deposit)| (deposit.client(), deposit.outstanding()));
+        let open_holds = self
+            .authorizations
+            .values()
+            .map(|auth| (auth.client, Some(auth.remaining)));
+        for (client, amount) in open_disputes.chain(open_holds) {
+            let held = expected_held.entry(client).or_default();
+            *held = amount
+                .and_then(|amount| held.checked_add(amount))
+                .ok_or(InvariantViolation::HeldOverflow(client))?;
+        }
+
+        let mut total = Some(Amount::ZERO);
+        for account in self.accounts.values() {
+            self.check_account(account)?;
+            let expected = expected_held.remove(&account.client).unwrap_or_default();
+            if account.held != expected {
+                return Err(InvariantViolation::HeldMismatch(
+                    account.client,
+                    account.held.into(),
+                    expected.into(),
+                ));
+            }
+            total = total
+                .zip(account.total())
+                .and_then(|(total, account)| total.checked_add(account));
+        }
+        if let Some((&client, &expected)) = expected_held.iter().next() {
+            return Err(InvariantViolation::HeldMismatch(
+                client,
+                dec!(0),
+                expected.into(),
+            ));
+        }
+        let total = total.map(Decimal::from);
+
+        if let Some(total) = total
+            && total.abs() < EXACT_LIMIT
+            && !self.ledger.overflow
+            && total != self.ledger.net_flow
+        {
+            return Err(InvariantViolation::MoneyNotConserved(
+                total,
+                self.ledger.net_flow,
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_transaction_invariants(&self, client: ClientId) -> Result<(), InvariantViolation> {
+        if let Some(account) = self.accounts.get(&client) {
+            self.check_account(account)?;
+        }
+        let ledger = &self.ledger;
+        if !ledger.overflow && ledger.balance_total != ledger.net_flow {
+            return Err(InvariantViolation::MoneyNotConserved(
+                ledger.balance_total,
+                ledger.net_flow,
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_account(&self, account: &Account) -> Result<(), InvariantViolation> {
+        let client = account.client;
+        if account.held < Amount::ZERO {
+            return Err(InvariantViolation::NegativeHeld(
+                client,
+                account.held.into(),
+            ));
+        }
+        if account.available.checked_add(account.held).is_none() {
+            return Err(InvariantViolation::TotalOverflow(client));
+        }
+        if account.closed && !(account.available.is_zero() && account.held.is_zero()) {
+            return Err(InvariantViolation::ClosedWithBalance(client));
+        }
+        let open = self
+            .ledger
+            .open_holds
+            .get(&client)
+            .copied()
+            .unwrap_or_default();
+        if account.held != open {
+            return Err(InvariantViolation::HeldMismatch(
+                client,
+                account.held.into(),
+                open,
+            ));
+        }
+        if account.locked && self.ledger.locked_at.get(&client) != Some(&Balances::of(account)) {
+            return Err(InvariantViolation::LockedAccountChanged(client));
+        }
+        Ok(())
+    }
+
+    /// Processes `batch` in order. In `Atomic` mode the first failing record
+    /// keeps its error, every other record gets `BatchRolledBack` and the
+    /// engine is left exactly as before the batch. Metrics and hooks have
+    /// already observed the records and are not rolled back.
+    pub fn process_batch(
+        &mut self,
+        batch: &[TransactionInput],
+        atomicity: Atomicity,
+    ) -> Vec<Result<(), PaymentError>> {
+        if atomicity == Atomicity::BestEffort {
+            return batch.iter().map(|tx| self.process(tx)).collect();
+        }
+
+        self.begin();
+        for (i, tx) in batch.iter().enumerate() {
+            if let Err(e) = self.process(tx) {
+                self.rollback();
+                let mut failure = Some(e);
+                return batch
+                    .iter()
+                    .enumerate()
+                    .map(|(j, tx)| match failure.take_if(|_| i == j) {
+                        Some(e) => Err(e),
+                        None => Err(PaymentError::BatchRolledBack(tx.tx)),
+                    })
+                    .collect();
+            }
+        }
+        self.commit();
+        batch.iter().map(|_| Ok(())).collect()
+    }
+
+    /// Opens a what-if fork. Transactions processed through it are applied
+    /// to this engine under the batch undo journal, so only the clients and
+    /// deposits they touch are copied, and everything is rolled back when the
+    /// fork is dropped. Subscribers see none of its events.
+    pub fn fork(&mut self) -> Fork<'_> {
+        self.begin();
+        Fork { engine: self }
+    }
+
+    /// Starts journaling so that `rollback` can undo everything up to the
+    /// matching `commit`.
+    fn begin(&mut self) {
+        assert!(self.journal.is_none(), "journal already open");
+        self.deposits.begin();
+        self.journal = Some(Journal {
+            seq: self.seq,
+            clock: self.clock,
+            expired: self.expired.len(),
+            risk_hits: self.risk.hits().len(),
+            net_flow: self.ledger.net_flow,
+            balance_total: self.ledger.balance_total,
+            overflow: self.ledger.overflow,
+            clients: HashMap::new(),
+            authorizations: HashMap::new(),
+            events: Vec::new(),
+        });
+    }
+
+    fn commit(&mut self) {
+        let Some(journal) = self.journal.take() else {
+            return;
+        };
+        self.deposits.commit();
+        self.deposits.expire(self.seq);
+        for event in &journal.events {
+            for subscriber in &mut self.subscribers {
+                subscriber.on_event(event);
+            }
+        }
+    }
+
+    fn rollback(&mut self) {
+        let Some(journal) = self.journal.take() else {
+            return;
+        };
+        self.deposits.rollback();
+        self.seq = journal.seq;
+        self.clock = journal.clock;
+        self.expired.truncate(journal.expired);
+        self.risk.truncate_hits(journal.risk_hits);
+        self.ledger.net_flow = journal.net_flow;
+        self.ledger.balance_total = journal.balance_total;
+        self.ledger.overflow = journal.overflow;
+
+        for (client, snapshot) in journal.clients {
+            restore(&mut self.accounts, client, snapshot.account);
+            restore(&mut self.ledger.open_holds, client, snapshot.open_holds);
+            restore(&mut self.ledger.locked_at, client, snapshot.locked_at);
+            self.risk.restore(client, snapshot.risk);
+        }
+        for (id, snapshot) in journal.authorizations {
+            if let Some(deadline) = self.authorizations.get(&id).and_then(|a| a.expires_at) {
+                self.expiry_queue.remove(&(deadline, id));
+            }
+            if let Some(deadline) = snapshot.authorization.as_ref().and_then(|a| a.expires_at)
+                && snapshot.queued
+            {
+                self.expiry_queue.insert((deadline, id));
+            }
+            restore(&mut self.authorizations, id, snapshot.authorization);
+        }
+    }
+
+    /// Saves `client`'s state into the open journal the first time the batch
+    /// touches it.
+    fn touch_client(&mut self, client: ClientId) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        journal
+            .clients
+            .entry(client)
+            .or_insert_with(|| ClientSnapshot {
+                account: self.accounts.get(&client).cloned(),
+                open_holds: self.ledger.open_holds.get(&client).copied(),
+                locked_at: self.ledger.locked_at.get(&client).copied(),
+                risk: self.risk.client_state(client),
+            });
+    }
+
+    fn touch_authorization(&mut self, id: TxId) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        journal.authorizations.entry(id).or_insert_with(|| {
+            let authorization = self.authorizations.get(&id).cloned();
+            let queued = authorization
+                .as_ref()
+                .and_then(|auth| auth.expires_at)
+                .is_some_and(|deadline| self.expiry_queue.contains(&(deadline, id)));
+            AuthorizationSnapshot {
+                authorization,
+                queued,
+            }
+        });
+    }
+
+    fn process_record(&mut self, tx: &TransactionInput) -> Result<(), PaymentError> {
+        self.touch_client(tx.client);
+        self.touch_authorization(tx.tx);
+        self.advance(tx);
+
+        let (client, id, provenance) = (tx.client, tx.tx, tx.provenance.as_ref());
+        let before = self.balances(client);
+        match self.apply(tx) {
+            Ok(kind) => {
+                let after = self.balances(client);
+                if after.locked && !before.locked {
+                    let unlocked = Balances {
+                        locked: false,
+                        ..after
+                    };
+                    self.emit(client, id, provenance, kind, before, unlocked);
+                    self.emit(
+                        client,
+                        id,
+                        provenance,
+                        EventKind::AccountLocked,
+                        unlocked,
+                        after,
+                    );
+                } else {
+                    self.emit(client, id, provenance, kind, before, after);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // Formatting the reason is a large part of a rejection's
+                // cost, so it is skipped when nobody will read it.
+                let reason = if self.subscribers.is_empty() {
+                    String::new()
+                } else {
+                    e.to_string()
+                };
+                let kind = EventKind::Rejected {
+                    tx_type: tx.tx_type,
+                    reason,
+                };
+                self.emit(client, id, provenance, kind, before, before);
+                let after = self.balances(client);
+                if after.locked && !before.locked {
+                    self.emit(
+                        client,
+                        id,
+                        provenance,
+                        EventKind::AccountLocked,
+                        before,
+                        after,
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Fails with `EventMismatch` if the event's `before` is not the
+    /// account's current balances, with `DuplicateTransaction` if a deposit or
+    /// authorization reuses a stored id, and with `Overflow` if a refund or
+    /// capture cannot be applied to what it names. `after` is taken as it is;
+    /// `check_invariants` tells whether the result adds up.
+    pub fn apply_event(&mut self, event: &Event) -> Result<(), PaymentError> {
+        if self.balances(event.client) != event.before {
+            return Err(PaymentError::EventMismatch(event.seq, event.client));
+        }
+        self.seq = self.seq.max(event.seq);
+
+        match event.kind {
+            EventKind::Rejected { .. } | EventKind::Replayed { .. } => return Ok(()),
+            EventKind::Deposited { amount } => {
+                if self.deposits.contains(event.tx) || self.deposits.was_evicted(event.tx) {
+                    return Err(PaymentError::DuplicateTransaction(event.tx));
+                }
+                self.deposits
+                    .insert(event.tx, event.client, amount.into(), event.seq);
+            }
+            EventKind::DisputeOpened { .. } => self.deposits.set_disputed(event.tx, true),
+            EventKind::DisputeResolved { .. } | EventKind::ChargedBack { .. } => {
+                self.deposits.set_disputed(event.tx, false);
+            }
+            EventKind::Refunded { amount } => self.deposits.add_refund(event.tx, amount.into())?,
+            EventKind::Authorized { amount, expires_at } => {
+                if self.authorizations.contains_key(&event.tx) {
+                    return Err(PaymentError::DuplicateTransaction(event.tx));
+                }
+                self.authorizations.insert(
+                    event.tx,
+                    StoredAuthorization {
+                        client: event.client,
+                        amount: amount.into(),
+                        remaining: amount.into(),
+                        expires_at,
+                    },
+                );
+                if let Some(deadline) = expires_at {
+                    self.expiry_queue.insert((deadline, event.tx));
+                }
+            }
+            EventKind::Captured { amount } => {
+                if let Some(auth) = self.authorizations.get_mut(&event.tx) {
+                    auth.remaining = auth
+                        .remaining
+                        .checked_sub(amount.into())
+                        .ok_or(PaymentError::Overflow(event.tx))?;
+                    if auth.remaining.is_zero() {
+                        self.remove_authorization(event.tx);
+                    }
+                }
+            }
+            EventKind::Voided { .. } | EventKind::HoldExpired { .. } => {
+                if self.authorizations.contains_key(&event.tx) {
+                    self.remove_authorization(event.tx);
+                }
+            }
+            EventKind::Withdrawn { .. }
+            | EventKind::AccountLocked
+            | EventKind::AccountOpened { .. }
+            | EventKind::AccountClosed { .. } => {}
+        }
+        self.ledger
+            .record(event.client, &event.kind, event.before, event.after);
+
+        let account = self
+            .accounts
+            .entry(event.client)
+            .or_insert_with(|| Account::new(event.client));
+        account.available = event.after.available.into();
+        account.held = event.after.held.into();
+        account.locked = event.after.locked;
+        account.closed |= matches!(event.kind, EventKind::AccountClosed { .. });
+        Ok(())
+    }
+
+    fn balances(&self, client: ClientId) -> Balances {
+        self.accounts
+            .get(&client)
+            .map(Balances::of)
+            .unwrap_or_default()
+    }
+
+    fn emit(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        provenance: Option<&Provenance>,
+        kind: EventKind,
+        before: Balances,
+        after: Balances,
+    ) {
+        self.ledger.record(client, &kind, before, after);
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let event = Event {
+            seq: self.seq,
+            client,
+            tx,
+            kind,
+            before,
+            after,
+            provenance: provenance.cloned(),
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.events.push(event);
+            return;
+        }
+        for subscriber in &mut self.subscribers {
+            subscriber.on_event(&event);
+        }
+    }
+
+    fn apply(&mut self, tx: &TransactionInput) -> Result<EventKind, PaymentError> {
+        if !self.config.enabled_types.contains(&tx.tx_type) {
+            return Err(PaymentError::TypeDisabled(tx.tx, tx.tx_type));
+        }
+        if let Some(account) = self.accounts.get(&tx.client) {
+            if account.closed {
+                return Err(PaymentError::AccountClosed(tx.client));
+            }
+            if account.locked {
+                return Err(PaymentError::AccountFrozen(tx.client));
+            }
+        }
+
+        let amount = tx
+            .amount
+            .map(|amount| self.normalize(tx.tx, amount))
+            .transpose()?;
+        // Checked before the risk rules, so they only ever see valid amounts.
+        if let Some(amount) = amount
+            && amount <= Amount::ZERO
+            && matches!(
+                tx.tx_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Authorize
+                    | TransactionType::Capture
+                    | TransactionType::Refund
+            )
+        {
+            return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+        }
+        let tx = &tx.with_amount(amount);
+
+        match self
+            .risk
+            .evaluate(tx, self.accounts.get(&tx.client), self.seq)
         {
-            return Err(PaymentError::AccountFrozen(tx.client));
+            Some(RiskAction::Freeze) => {
+                self.risk.keep_hits();
+                if let Some(account) = self.accounts.get_mut(&tx.client) {
+                    account.locked = true;
+                }
+                return Err(PaymentError::RiskFrozen(tx.client, tx.tx));
+            }
+            Some(RiskAction::Reject) => {
+                self.risk.keep_hits();
+                return Err(PaymentError::RiskRejected(tx.tx));
+            }
+            Some(RiskAction::Flag) | None => {}
+        }
+
+        let ctx = HookContext {
+            tx,
+            account: self.accounts.get(&tx.client),
+            deposit: self.deposits.get(tx.tx),
+            seq: self.seq,
+            clock: self.clock,
+        };
+        for hook in &mut self.hooks {
+            hook.before_apply(&ctx)
+                .map_err(|reason| PaymentError::Vetoed(tx.tx, reason))?;
         }
 
-        match tx.tx_type {
+        let kind = match tx.tx_type {
             TransactionType::Deposit => self.deposit(tx),
             TransactionType::Withdrawal => self.withdrawal(tx),
             TransactionType::Dispute => self.dispute(tx),
             TransactionType::Resolve => self.resolve(tx),
             TransactionType::Chargeback => self.chargeback(tx),
+            TransactionType::Authorize => self.authorize(tx),
+            TransactionType::Capture => self.capture(tx),
+            TransactionType::Void => self.void(tx),
+            TransactionType::Refund => self.refund(tx),
+            TransactionType::Open => self.open(tx),
+            TransactionType::Close => self.close(tx),
+        }?;
+
+        let ctx = HookContext {
+            tx,
+            account: self.accounts.get(&tx.client),
+            deposit: self.deposits.get(tx.tx),
+            seq: self.seq,
+            clock: self.clock,
+        };
+        for hook in &mut self.hooks {
+            hook.after_apply(&ctx);
         }
+        self.risk.record(tx);
+
+        Ok(kind)
     }
 
     pub fn accounts(&self) -> impl Iterator<Item = &Account> {
         self.accounts.values()
     }
 
-    fn deposit(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
-        let amount = tx.amount.ok_or(PaymentError::MissingAmount(tx.tx))?;
-        if amount <= dec!(0) {
-            return Err(PaymentError::InvalidAmount(tx.tx, amount));
+    pub fn deposit_count(&self) -> usize {
+        self.deposits.len()
+    }
+
+    pub fn evicted_deposits(&self) -> u64 {
+        self.deposits.evicted()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            accounts: hash_map_bytes(&self.accounts),
+            deposits: self.deposits.memory(),
+            authorizations: hash_map_bytes(&self.authorizations)
+                + self.expiry_queue.len() * std::mem::size_of::<(u64, TxId)>(),
         }
+    }
+
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    pub fn risk_report(&self) -> &[RiskHit] {
+        self.risk.hits()
+    }
+
+    pub fn is_flagged(&self, client: ClientId) -> bool {
+        self.risk.flagged().contains(&client)
+    }
+
+    pub fn take_expired_holds(&mut self) -> Vec<ExpiredHold> {
+        std::mem::take(&mut self.expired)
+    }
 
-        if self.deposits.contains_key(&tx.tx) {
-            return Err(PaymentError::DuplicateTransaction(tx.tx));
+    fn normalize(&self, tx: TxId, amount: Decimal) -> Result<Amount, PaymentError> {
+        let amount = if amount.normalize().scale() > AMOUNT_SCALE {
+            match self.config.precision {
+                PrecisionPolicy::Reject => {
+                    return Err(PaymentError::ExcessPrecision(tx, amount));
+                }
+                PrecisionPolicy::RoundHalfEven => amount
+                    .round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointNearestEven),
+                PrecisionPolicy::Truncate => {
+                    amount.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::ToZero)
+                }
+            }
+        } else {
+            amount
+        };
+
+        if let Some(max) = self.config.max_amount
+            && amount > max
+        {
+            return Err(PaymentError::AmountTooLarge(tx, amount, max));
         }
 
-        let account = self
-            .accounts
-            .entry(tx.client)
-            .or_insert_with(|| Account::new(tx.client));
-        account.available += amount;
+        Ok(Amount::from(amount))
+    }
 
-        self.deposits.insert(
-            tx.tx,
-            StoredDeposit {
-                client: tx.client,
-                amount,
-                disputed: false,
+    fn advance(&mut self, tx: &TransactionInput) {
+        self.seq += 1;
+        self.deposits.expire(self.seq);
+        if let Some(timestamp) = tx.timestamp {
+            self.clock = Some(self.clock.map_or(timestamp, |now| now.max(timestamp)));
+        }
+
+        let now = match self.config.hold_expiry {
+            HoldExpiry::Never => return,
+            HoldExpiry::AfterTransactions(_) => self.seq,
+            HoldExpiry::AfterSeconds(_) => match self.clock {
+                Some(now) => now,
+                None => return,
             },
-        );
+        };
+
+        while let Some(&(deadline, id)) = self.expiry_queue.first() {
+            if deadline >= now {
+                break;
+            }
+            self.touch_authorization(id);
+            self.expiry_queue.pop_first();
+
+            let Some(client) = self.authorizations.get(&id).map(|auth| auth.client) else {
+                continue;
+            };
+            self.touch_client(client);
+            let auth = &self.authorizations[&id];
+            let account = self
+                .accounts
+                .get_mut(&auth.client)
+                .expect("account must exist if authorization exists");
+            if account.locked {
+                continue;
+            }
+            let Some((available, held)) = account.checked_adjust(auth.remaining, -auth.remaining)
+            else {
+                continue;
+            };
+
+            let before = Balances::of(account);
+            let auth = self.authorizations.remove(&id).expect("checked above");
+            account.available = available;
+            account.held = held;
+            let after = Balances::of(account);
+
+            self.expired.push(ExpiredHold {
+                authorization: id,
+                client: auth.client,
+                amount: auth.remaining,
+            });
+            let kind = EventKind::HoldExpired {
+                amount: auth.remaining.into(),
+            };
+            self.emit(auth.client, id, None, kind, before, after);
+        }
+    }
 
-        Ok(())
+    fn hold_deadline(&self) -> Option<u64> {
+        match self.config.hold_expiry {
+            HoldExpiry::Never => None,
+            HoldExpiry::AfterTransactions(n) => Some(self.seq.saturating_add(n)),
+            HoldExpiry::AfterSeconds(secs) => self.clock.map(|now| now.saturating_add(secs)),
+        }
     }
 
-    fn withdrawal(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
-        let amount = tx.amount.ok_or(PaymentError::MissingAmount(tx.tx))?;
-        if amount <= dec!(0) {
-            return Err(PaymentError::InvalidAmount(tx.tx, amount));
+    fn deposit(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let amount = required_amount(tx)?;
+
+        if let Some(kind) = self.duplicate(tx, amount)? {
+            return Ok(kind);
         }
 
-        let account = self
+        let new = Account::new(tx.client);
+        let (available, _) = self
             .accounts
-            .entry(tx.client)
-            .or_insert_with(|| Account::new(tx.client));
+            .get(&tx.client)
+            .unwrap_or(&new)
+            .checked_adjust(amount, Amount::ZERO)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+        self.accounts.entry(tx.client).or_insert(new).available = available;
+
+        self.deposits.insert(tx.tx, tx.client, amount, self.seq);
+
+        Ok(EventKind::Deposited {
+            amount: amount.into(),
+        })
+    }
+
+    fn withdrawal(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let amount = required_amount(tx)?;
+
+        let account = self.debit_account(tx.client, amount)?;
         if account.available < amount {
             return Err(PaymentError::InsufficientFunds(
                 tx.client,
-                amount,
-                account.available,
+                amount.into(),
+                account.available.into(),
             ));
         }
 
-        account.available -= amount;
-        Ok(())
+        let (available, _) = account
+            .checked_adjust(-amount, Amount::ZERO)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+        account.available = available;
+        Ok(EventKind::Withdrawn {
+            amount: amount.into(),
+        })
     }
 
-    fn dispute(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
+    fn dispute(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
         let deposit = self
             .deposits
-            .get_mut(&tx.tx)
+            .get(tx.tx)
+            .filter(|deposit| deposit.client() == tx.client)
             .ok_or(PaymentError::TransactionNotFound(tx.tx))?;
 
-        if deposit.client != tx.client {
-            return Err(PaymentError::TransactionNotFound(tx.tx));
+        if deposit.disputed() {
+            return Err(PaymentError::AlreadyUnderDispute(tx.tx));
         }
 
-        if deposit.disputed {
-            return Err(PaymentError::AlreadyUnderDispute(tx.tx));
+        let amount = deposit.outstanding().ok_or(PaymentError::Overflow(tx.tx))?;
+        if amount.is_zero() {
+            return Err(PaymentError::FullyRefunded(tx.tx));
         }
 
-        deposit.disputed = true;
         let account = self
             .accounts
             .get_mut(&tx.client)
             .expect("account must exist if deposit exists");
-        account.available -= deposit.amount;
-        account.held += deposit.amount;
+        let (available, held) = account
+            .checked_adjust(-amount, amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
 
-        Ok(())
+        self.deposits.set_disputed(tx.tx, true);
+        account.available = available;
+        account.held = held;
+
+        Ok(EventKind::DisputeOpened {
+            amount: amount.into(),
+        })
     }
 
-    fn resolve(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
+    fn resolve(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
         let deposit = self
             .deposits
-            .get_mut(&tx.tx)
+            .get(tx.tx)
+            .filter(|deposit| deposit.client() == tx.client)
             .ok_or(PaymentError::TransactionNotFound(tx.tx))?;
 
-        if deposit.client != tx.client {
-            return Err(PaymentError::TransactionNotFound(tx.tx));
+        if !deposit.disputed() {
+            return Err(PaymentError::NotUnderDispute(tx.tx));
         }
 
-        if !deposit.disputed {
+        let amount = deposit.outstanding().ok_or(PaymentError::Overflow(tx.tx))?;
+        let account = self
+            .accounts
+            .get_mut(&tx.client)
+            .expect("account must exist if deposit exists");
+        let (available, held) = account
+            .checked_adjust(amount, -amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+
+        self.deposits.set_disputed(tx.tx, false);
+        account.available = available;
+        account.held = held;
+
+        Ok(EventKind::DisputeResolved {
+            amount: amount.into(),
+        })
+    }
+
+    fn chargeback(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let deposit = self
+            .deposits
+            .get(tx.tx)
+            .filter(|deposit| deposit.client() == tx.client)
+            .ok_or(PaymentError::TransactionNotFound(tx.tx))?;
+
+        if !deposit.disputed() {
             return Err(PaymentError::NotUnderDispute(tx.tx));
         }
 
-        deposit.disputed = false;
+        let amount = deposit.outstanding().ok_or(PaymentError::Overflow(tx.tx))?;
         let account = self
             .accounts
             .get_mut(&tx.client)
             .expect("account must exist if deposit exists");
-        account.held -= deposit.amount;
-        account.available += deposit.amount;
+        let (_, held) = account
+            .checked_adjust(Amount::ZERO, -amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
 
-        Ok(())
+        self.deposits.set_disputed(tx.tx, false);
+        account.held = held;
+        account.locked = true;
+
+        Ok(EventKind::ChargedBack {
+            amount: amount.into(),
+        })
     }
 
-    fn chargeback(&mut self, tx: &TransactionRecord) -> Result<(), PaymentError> {
+    fn refund(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
         let deposit = self
             .deposits
-            .get_mut(&tx.tx)
+            .get(tx.tx)
+            .filter(|deposit| deposit.client() == tx.client)
             .ok_or(PaymentError::TransactionNotFound(tx.tx))?;
 
-        if deposit.client != tx.client {
-            return Err(PaymentError::TransactionNotFound(tx.tx));
+        if deposit.disputed() {
+            return Err(PaymentError::AlreadyUnderDispute(tx.tx));
         }
 
-        if !deposit.disputed {
-            return Err(PaymentError::NotUnderDispute(tx.tx));
+        let outstanding = deposit.outstanding().ok_or(PaymentError::Overflow(tx.tx))?;
+        if outstanding.is_zero() {
+            return Err(PaymentError::FullyRefunded(tx.tx));
+        }
+
+        let amount = match tx.amount {
+            Some(amount) if amount <= Amount::ZERO => {
+                return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+            }
+            Some(amount) => amount,
+            None => outstanding,
+        };
+        if amount > outstanding {
+            return Err(PaymentError::RefundExceedsDeposit(
+                tx.tx,
+                amount.into(),
+                outstanding.into(),
+            ));
         }
 
-        deposit.disputed = false;
         let account = self
             .accounts
             .get_mut(&tx.client)
             .expect("account must exist if deposit exists");
-        account.held -= deposit.amount;
-        account.locked = true;
+        if account.available < amount {
+            return Err(PaymentError::InsufficientFunds(
+                tx.client,
+                amount.into(),
+                account.available.into(),
+            ));
+        }
 
-        Ok(())
+        let (available, _) = account
+            .checked_adjust(-amount, Amount::ZERO)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+        self.deposits.add_refund(tx.tx, amount)?;
+        account.available = available;
+
+        Ok(EventKind::Refunded {
+            amount: amount.into(),
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::transaction::{TransactionRecord, TransactionType};
+    fn authorize(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let amount = required_amount(tx)?;
 
-    fn tx(
-        tx_type: TransactionType,
-        client: u16,
-        tx: u32,
-        amount: Option<Decimal>,
-    ) -> TransactionRecord {
-        TransactionRecord {
-            tx_type,
-            client,
-            tx,
+        if let Some(kind) = self.duplicate(tx, amount)? {
+            return Ok(kind);
+        }
+
+        let expires_at = self.hold_deadline();
+        let account = self.debit_account(tx.client, amount)?;
+        if account.available < amount {
+            return Err(PaymentError::InsufficientFunds(
+                tx.client,
+                amount.into(),
+                account.available.into(),
+            ));
+        }
+
+        let (available, held) = account
+            .checked_adjust(-amount, amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+        account.available = available;
+        account.held = held;
+
+        self.authorizations.insert(
+            tx.tx,
+            StoredAuthorization {
+                client: tx.client,
+                amount,
+                remaining: amount,
+                expires_at,
+            },
+        );
+        if let Some(deadline) = expires_at {
+            self.expiry_queue.insert((deadline, tx.tx));
+        }
+
+        Ok(EventKind::Authorized {
+            amount: amount.into(),
+            expires_at,
+        })
+    }
+
+    fn capture(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let auth = self
+            .authorizations
+            .get_mut(&tx.tx)
+            .filter(|auth| auth.client == tx.client)
+            .ok_or(PaymentError::AuthorizationNotFound(tx.tx))?;
+
+        let amount = match tx.amount {
+            Some(amount) if amount <= Amount::ZERO => {
+                return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+            }
+            Some(amount) => amount,
+            None => auth.remaining,
+        };
+        if amount > auth.remaining {
+            return Err(PaymentError::CaptureExceedsHold(
+                tx.tx,
+                amount.into(),
+                auth.remaining.into(),
+            ));
+        }
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client)
+            .expect("account must exist if authorization exists");
+        let (_, held) = account
+            .checked_adjust(Amount::ZERO, -amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+        let remaining = auth
+            .remaining
+            .checked_sub(amount)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+
+        account.held = held;
+        auth.remaining = remaining;
+        if auth.remaining.is_zero() {
+            self.remove_authorization(tx.tx);
+        }
+
+        Ok(EventKind::Captured {
+            amount: amount.into(),
+        })
+    }
+
+    fn void(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        let remaining = self
+            .authorizations
+            .get(&tx.tx)
+            .filter(|auth| auth.client == tx.client)
+            .ok_or(PaymentError::AuthorizationNotFound(tx.tx))?
+            .remaining;
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client)
+            .expect("account must exist if authorization exists");
+        let (available, held) = account
+            .checked_adjust(remaining, -remaining)
+            .ok_or(PaymentError::Overflow(tx.tx))?;
+
+        account.available = available;
+        account.held = held;
+        self.remove_authorization(tx.tx);
+
+        Ok(EventKind::Voided {
+            amount: remaining.into(),
+        })
+    }
+
+    fn open(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        if let Some(amount) = tx.amount {
+            return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+        }
+        if self.accounts.contains_key(&tx.client) {
+            return Err(PaymentError::AccountExists(tx.client));
+        }
+
+        self.accounts.insert(tx.client, Account::new(tx.client));
+        Ok(EventKind::AccountOpened {
+            metadata: tx.metadata.clone(),
+        })
+    }
+
+    fn close(&mut self, tx: &TransactionRecord) -> Result<EventKind, PaymentError> {
+        if let Some(amount) = tx.amount {
+            return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+        }
+        let account = self
+            .accounts
+            .get_mut(&tx.client)
+            .ok_or(PaymentError::AccountNotFound(tx.client))?;
+        if !account.available.is_zero() || !account.held.is_zero() {
+            return Err(PaymentError::AccountNotEmpty(
+                tx.client,
+                account.available.into(),
+                account.held.into(),
+            ));
+        }
+
+        account.closed = true;
+        Ok(EventKind::AccountClosed {
+            metadata: tx.metadata.clone(),
+        })
+    }
+
+    /// The account a withdrawal or authorization draws on. Unknown clients
+    /// have no funds and do not get an account.
+    fn debit_account(
+        &mut self,
+        client: ClientId,
+        amount: Amount,
+    ) -> Result<&mut Account, PaymentError> {
+        self.accounts
+            .get_mut(&client)
+            .ok_or(PaymentError::InsufficientFunds(
+                client,
+                amount.into(),
+                dec!(0),
+            ))
+    }
+
+    /// Checks a deposit or authorization id against stored ones. Returns the
+    /// event for an ignored identical resubmission.
+    fn duplicate(
+        &self,
+        tx: &TransactionRecord,
+        amount: Amount,
+    ) -> Result<Option<EventKind>, PaymentError> {
+        let stored = match (self.deposits.get(tx.tx), self.authorizations.get(&tx.tx)) {
+            (Some(deposit), _) => (TransactionType::Deposit, deposit.client(), deposit.amount()),
+            (None, Some(auth)) => (TransactionType::Authorize, auth.client, auth.amount),
+            // Nothing is left to compare an evicted deposit with, so it is
+            // always a duplicate.
+            (None, None) if self.deposits.was_evicted(tx.tx) => {
+                return Err(PaymentError::DuplicateTransaction(tx.tx));
+            }
+            (None, None) => return Ok(None),
+        };
+        if self.config.duplicates == DuplicatePolicy::Idempotent
+            && stored == (tx.tx_type, tx.client, amount)
+        {
+            return Ok(Some(EventKind::Replayed {
+                tx_type: tx.tx_type,
+            }));
+        }
+        Err(PaymentError::DuplicateTransaction(tx.tx))
+    }
+
+    fn remove_authorization(&mut self, id: TxId) -> StoredAuthorization {
+        let auth = self
+            .authorizations
+            .remove(&id)
+            .expect("authorization must exist");
+        if let Some(deadline) = auth.expires_at {
+            self.expiry_queue.remove(&(deadline, id));
+        }
+        auth
+    }
+}
+
+/// A what-if view of an engine, from [`PaymentEngine::fork`].
+pub struct Fork<'a> {
+    engine: &'a mut PaymentEngine,
+}
+
+impl Fork<'_> {
+    /// Processes `tx` without recording metrics. Hooks still run.
+    pub fn process(&mut self, tx: &TransactionInput) -> Result<(), PaymentError> {
+        let metrics = self.engine.metrics.take();
+        let result = self.engine.process(tx);
+        self.engine.metrics = metrics;
+        result
+    }
+
+    /// The engine as the fork's transactions have left it.
+    pub fn engine(&self) -> &PaymentEngine {
+        self.engine
+    }
+
+    /// Changed accounts, ordered by client. Fails with `BalanceOverflow` if
+    /// a difference is not representable, which only balances loaded from an
+    /// event log can cause.
+    pub fn deltas(&self) -> Result<Vec<AccountDelta>, PaymentError> {
+        let journal = self.engine.journal.as_ref().expect("fork keeps a journal");
+        let mut deltas = Vec::new();
+        for (&client, snapshot) in &journal.clients {
+            let new = Account::new(client);
+            let before = snapshot.account.as_ref().unwrap_or(&new);
+            let after = self.engine.accounts.get(&client).unwrap_or(&new);
+            if Balances::of(before) == Balances::of(after) {
+                continue;
+            }
+            let delta = |after: Option<Amount>, before: Option<Amount>| {
+                after
+                    .zip(before)
+                    .and_then(|(after, before)| after.checked_sub(before))
+                    .map(Decimal::from)
+                    .ok_or(PaymentError::BalanceOverflow(client))
+            };
+            deltas.push(AccountDelta {
+                client,
+                available: delta(Some(after.available), Some(before.available))?,
+                held: delta(Some(after.held), Some(before.held))?,
+                total: delta(after.total(), before.total())?,
+                locked: after.locked,
+            });
+        }
+        deltas.sort_by_key(|delta| delta.client);
+        Ok(deltas)
+    }
+}
+
+impl Drop for Fork<'_> {
+    fn drop(&mut self) {
+        self.engine.rollback();
+    }
+}
+
+/// Puts `value` back under `key`, or removes the key if it was absent.
+fn restore<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+/// The normalized amount of a deposit, withdrawal or authorization.
+fn required_amount(tx: &TransactionRecord) -> Result<Amount, PaymentError> {
+    let amount = tx.amount.ok_or(PaymentError::MissingAmount(tx.tx))?;
+    if amount <= Amount::ZERO {
+        return Err(PaymentError::InvalidAmount(tx.tx, amount.into()));
+    }
+    Ok(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::sync::mpsc;
+    use crate::transaction::{ClientId, Provenance, TransactionInput, TransactionType, TxId};
+
+    fn tx(
+        tx_type: TransactionType,
+        client: u64,
+        tx: u64,
+        amount: Option<Decimal>,
+    ) -> TransactionInput {
+        TransactionInput {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
             amount,
+            timestamp: None,
+            metadata: None,
+            provenance: None,
+        }
+    }
+
+    fn get_account(engine: &PaymentEngine, client: u64) -> &Account {
+        engine.accounts().find(|a| a.client == ClientId(client)).unwrap()
+    }
+
+    #[test]
+    fn deposit_increases_available() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.total().unwrap(), dec!(10));
+    }
+
+    #[test]
+    fn multiple_deposits_accumulate() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(5)))).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(15));
+    }
+
+    #[test]
+    fn duplicate_deposit_tx_id_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(99))));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+    }
+
+    #[test]
+    fn idempotent_duplicates_ignore_identical_resubmissions() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder()
+            .duplicates(DuplicatePolicy::Idempotent)
+            .subscriber(sender)
+            .build();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(3)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10.0)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(3)))).unwrap();
+
+        for (tx_type, client, id, amount) in [
+            (TransactionType::Deposit, 1, 1, dec!(11)),
+            (TransactionType::Deposit, 2, 1, dec!(10)),
+            (TransactionType::Authorize, 1, 1, dec!(10)),
+            (TransactionType::Deposit, 1, 2, dec!(3)),
+        ] {
+            let result = engine.process(&tx(tx_type, client, id, Some(amount)));
+            assert!(matches!(result, Err(PaymentError::DuplicateTransaction(_))));
+        }
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(7));
+        assert_eq!(account.held, dec!(3));
+        let replayed = receiver
+            .try_iter()
+            .filter(|event| matches!(event.kind, EventKind::Replayed { .. }))
+            .count();
+        assert_eq!(replayed, 2);
+    }
+
+    #[test]
+    fn disabled_types_are_err() {
+        let mut engine = PaymentEngine::builder()
+            .enabled_types([TransactionType::Deposit, TransactionType::Withdrawal])
+            .build();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Dispute, 1, 1, None));
+
+        assert!(matches!(
+            result,
+            Err(PaymentError::TypeDisabled(TxId(1), TransactionType::Dispute))
+        ));
+        assert_eq!(get_account(&engine, 1).held, dec!(0));
+    }
+
+    #[test]
+    fn rejected_debits_create_no_account() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(&tx(TransactionType::Withdrawal, 1, 1, Some(dec!(10))));
+        assert!(matches!(result, Err(PaymentError::InsufficientFunds(..))));
+        let result = engine.process(&tx(TransactionType::Authorize, 2, 2, Some(dec!(10))));
+        assert!(matches!(result, Err(PaymentError::InsufficientFunds(..))));
+        let result = engine.process(&tx(TransactionType::Deposit, 3, 3, Some(dec!(0))));
+        assert!(matches!(result, Err(PaymentError::InvalidAmount(..))));
+
+        assert_eq!(engine.accounts().count(), 0);
+    }
+
+    #[test]
+    fn open_creates_empty_account() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder().subscriber(sender).build();
+        let open = TransactionInput {
+            metadata: Some("kyc=ok".to_string()),
+            ..tx(TransactionType::Open, 1, 1, None)
+        };
+        engine.process(&open).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.total().unwrap(), dec!(0));
+        assert!(!account.closed);
+        let result = engine.process(&tx(TransactionType::Open, 1, 2, None));
+        assert!(matches!(result, Err(PaymentError::AccountExists(ClientId(1)))));
+        let result = engine.process(&tx(TransactionType::Open, 2, 3, Some(dec!(1))));
+        assert!(matches!(result, Err(PaymentError::InvalidAmount(TxId(3), _))));
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            event.kind,
+            EventKind::AccountOpened {
+                metadata: Some("kyc=ok".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn close_requires_zero_balances() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(&tx(TransactionType::Close, 1, 1, None));
+        assert!(matches!(result, Err(PaymentError::AccountNotFound(ClientId(1)))));
+
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 2, None)).unwrap();
+        let result = engine.process(&tx(TransactionType::Close, 1, 3, None));
+        assert!(matches!(result, Err(PaymentError::AccountNotEmpty(ClientId(1), _, _))));
+
+        engine.process(&tx(TransactionType::Resolve, 1, 2, None)).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 4, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Close, 1, 5, None)).unwrap();
+        assert!(get_account(&engine, 1).closed);
+        engine.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn closed_account_rejects_everything() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Open, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Close, 1, 2, None)).unwrap();
+
+        for record in [
+            tx(TransactionType::Deposit, 1, 3, Some(dec!(5))),
+            tx(TransactionType::Withdrawal, 1, 4, Some(dec!(5))),
+            tx(TransactionType::Open, 1, 5, None),
+            tx(TransactionType::Close, 1, 6, None),
+        ] {
+            let result = engine.process(&record);
+            assert!(matches!(result, Err(PaymentError::AccountClosed(ClientId(1)))));
         }
+        let account = get_account(&engine, 1);
+        assert!(account.closed && !account.locked);
+        assert_eq!(account.total().unwrap(), dec!(0));
+    }
+
+    #[test]
+    fn config_from_toml() {
+        let config = EngineConfig::from_toml(
+            r#"
+            precision = "round"
+            max_amount = "5000"
+            hold_expiry = { after_transactions = 10 }
+            dispute_horizon = 1000000
+            enabled_types = ["deposit", "withdrawal", "dispute", "resolve", "chargeback"]
+            duplicates = "idempotent"
+
+            [[risk.checks]]
+            rule = "max_withdrawals"
+            count = 3
+            window = { transactions = 10 }
+            action = "reject"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.precision, PrecisionPolicy::RoundHalfEven);
+        assert_eq!(config.max_amount, Some(dec!(5000)));
+        assert_eq!(config.hold_expiry, HoldExpiry::AfterTransactions(10));
+        assert_eq!(config.dispute_horizon, Some(1_000_000));
+        assert_eq!(config.enabled_types.len(), 5);
+        assert!(!config.enabled_types.contains(&TransactionType::Refund));
+        assert_eq!(config.duplicates, DuplicatePolicy::Idempotent);
+        assert_eq!(config.risk.checks.len(), 1);
+
+        assert_eq!(EngineConfig::from_toml("").unwrap(), EngineConfig::default());
+        assert!(EngineConfig::from_toml("precison = \"round\"").is_err());
+    }
+
+    fn sorted_accounts(engine: &PaymentEngine) -> Vec<Account> {
+        let mut accounts: Vec<Account> = engine.accounts().cloned().collect();
+        accounts.sort_by_key(|account| account.client);
+        accounts
+    }
+
+    #[test]
+    fn atomic_batch_failure_rolls_back_everything() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder()
+            .hold_expiry(HoldExpiry::AfterTransactions(1))
+            .duplicates(DuplicatePolicy::Idempotent)
+            .subscriber(sender)
+            .build();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 2, None)).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 3, Some(dec!(2)))).unwrap();
+        let accounts = sorted_accounts(&engine);
+        let deposits = engine.deposits.clone();
+        let events = receiver.try_iter().count();
+
+        let batch = [
+            tx(TransactionType::Deposit, 2, 4, Some(dec!(7))),
+            tx(TransactionType::Resolve, 1, 2, None),
+            tx(TransactionType::Dispute, 1, 1, None),
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            tx(TransactionType::Withdrawal, 2, 5, Some(dec!(8))),
+        ];
+        let results = engine.process_batch(&batch, Atomicity::Atomic);
+        assert!(matches!(results[0], Err(PaymentError::BatchRolledBack(TxId(4)))));
+        assert!(matches!(results[3], Err(PaymentError::BatchRolledBack(TxId(1)))));
+        assert!(matches!(results[4], Err(PaymentError::InsufficientFunds(..))));
+        assert_eq!(results.len(), 5);
+
+        assert_eq!(sorted_accounts(&engine), accounts);
+        assert_eq!(engine.deposits, deposits);
+        assert_eq!(engine.seq, 4);
+        assert_eq!(get_account(&engine, 1).held, dec!(7));
+        assert!(engine.take_expired_holds().is_empty());
+        assert_eq!(receiver.try_iter().count(), 0);
+        assert_eq!(events, 4);
+        engine.check_invariants().unwrap();
+
+        engine.process(&tx(TransactionType::Resolve, 1, 2, None)).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 4, Some(dec!(70)))).unwrap();
+        assert_eq!(get_account(&engine, 2).available, dec!(70));
+        assert_eq!(engine.take_expired_holds().len(), 1);
+    }
+
+    #[test]
+    fn atomic_batch_success_commits_and_delivers_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder().subscriber(sender).build();
+        let batch = [
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            tx(TransactionType::Withdrawal, 1, 2, Some(dec!(4))),
+            tx(TransactionType::Dispute, 1, 1, None),
+        ];
+        let results = engine.process_batch(&batch, Atomicity::Atomic);
+
+        assert!(results.iter().all(Result::is_ok));
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(-4));
+        assert_eq!(account.held, dec!(10));
+        let seqs: Vec<u64> = receiver.try_iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+        engine.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn fork_reports_deltas_and_leaves_engine_unchanged() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder().subscriber(sender).metrics(true).build();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let accounts = sorted_accounts(&engine);
+        let deposits = engine.deposits.clone();
+        receiver.try_iter().for_each(drop);
+
+        let mut fork = engine.fork();
+        fork.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+        fork.process(&tx(TransactionType::Deposit, 3, 3, Some(dec!(2)))).unwrap();
+        fork.process(&tx(TransactionType::Withdrawal, 2, 4, Some(dec!(9)))).unwrap_err();
+        assert_eq!(get_account(fork.engine(), 3).available, dec!(2));
+        assert_eq!(
+            fork.deltas().unwrap(),
+            vec![
+                AccountDelta { client: ClientId(1), available: dec!(0), held: dec!(-10), total: dec!(-10), locked: true },
+                AccountDelta { client: ClientId(3), available: dec!(2), held: dec!(0), total: dec!(2), locked: false },
+            ]
+        );
+        drop(fork);
+
+        assert_eq!(sorted_accounts(&engine), accounts);
+        assert_eq!(engine.deposits, deposits);
+        assert_eq!(receiver.try_iter().count(), 0);
+        assert_eq!(engine.metrics().unwrap().latency().count(), 3);
+        engine.check_invariants().unwrap();
+        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 3, 3, Some(dec!(2)))).unwrap();
+    }
+
+    #[test]
+    fn errors_and_events_carry_provenance() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder().subscriber(sender).metrics(true).build();
+        let provenance = Provenance { file: Some("in.csv".into()), line: 7, byte: 120 };
+        let record = TransactionInput {
+            provenance: Some(provenance.clone()),
+            ..tx(TransactionType::Withdrawal, 1, 1, Some(dec!(5)))
+        };
+
+        let err = engine.process(&record).unwrap_err();
+        assert_eq!(err.provenance(), Some(&provenance));
+        assert!(matches!(err.inner(), PaymentError::InsufficientFunds(..)));
+        assert_eq!(err.kind(), "insufficient_funds");
+        assert_eq!(err.to_string(), "in.csv:7 (byte 120): insufficient funds for client 1: need 5, have 0");
+        assert_eq!(engine.metrics().unwrap().rejections("insufficient_funds"), 1);
+        assert_eq!(receiver.try_recv().unwrap().provenance, Some(provenance));
+        assert!(engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(5)))).unwrap_err().provenance().is_none());
+    }
+
+    #[test]
+    fn best_effort_batch_keeps_successes() {
+        let mut engine = PaymentEngine::new();
+        let batch = [
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            tx(TransactionType::Withdrawal, 1, 2, Some(dec!(40))),
+            tx(TransactionType::Deposit, 1, 3, Some(dec!(5))),
+        ];
+        let results = engine.process_batch(&batch, Atomicity::BestEffort);
+
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(matches!(results[1], Err(PaymentError::InsufficientFunds(..))));
+        assert_eq!(get_account(&engine, 1).available, dec!(15));
+    }
+
+    #[test]
+    fn withdrawal_decreases_available() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(4)))).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(6));
+        assert_eq!(account.total().unwrap(), dec!(6));
+    }
+
+    #[test]
+    fn withdrawal_insufficient_funds_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(10))));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(5));
+    }
+
+    #[test]
+    fn withdrawal_exact_balance() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(7)))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(7)))).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(0));
+    }
+
+    #[test]
+    fn dispute_moves_to_held() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(10));
+        assert_eq!(account.total().unwrap(), dec!(10));
+    }
+
+    #[test]
+    fn dispute_nonexistent_tx_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Dispute, 1, 999, None));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+    }
+
+    #[test]
+    fn dispute_already_disputed_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let result = engine.process(&tx(TransactionType::Dispute, 1, 1, None));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.held, dec!(10));
+    }
+
+    #[test]
+    fn dispute_wrong_client_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(5)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Dispute, 2, 1, None));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+    }
+
+    #[test]
+    fn resolve_moves_back_to_available() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(10));
+    }
+
+    #[test]
+    fn resolve_not_disputed_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Resolve, 1, 1, None));
+
+        assert!(result.is_err());
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(10));
+    }
+
+    #[test]
+    fn chargeback_removes_held_and_locks() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(0));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn frozen_account_rejects_all() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+
+        assert!(engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(50)))).is_err());
+        assert!(engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(1)))).is_err());
+
+        let account = get_account(&engine, 1);
+        assert!(account.locked);
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(0));
+    }
+
+    #[test]
+    fn multiple_clients_independent() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(20)))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(5)))).unwrap();
+
+        let a1 = get_account(&engine, 1);
+        let a2 = get_account(&engine, 2);
+        assert_eq!(a1.available, dec!(5));
+        assert_eq!(a2.available, dec!(20));
+    }
+
+    #[test]
+    fn full_dispute_resolve_lifecycle() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(100)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(50)))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(30)))).unwrap();
+
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(20));
+        assert_eq!(account.held, dec!(100));
+        assert_eq!(account.total().unwrap(), dec!(120));
+
+        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(120));
+        assert_eq!(account.held, dec!(0));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn full_dispute_chargeback_lifecycle() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(100)))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(40)))).unwrap();
+
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(-40));
+        assert_eq!(account.held, dec!(100));
+
+        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(-40));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(-40));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn re_dispute_after_resolve() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(50)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(50));
+    }
+
+    #[test]
+    fn deposit_missing_amount_is_err() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, None));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn withdrawal_missing_amount_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Withdrawal, 1, 2, None));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn deposit_zero_amount_is_err() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(0))));
+        assert!(result.is_err());
     }
 
-    fn get_account(engine: &PaymentEngine, client: u16) -> &Account {
-        engine.accounts().find(|a| a.client == client).unwrap()
+    #[test]
+    fn deposit_negative_amount_is_err() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(-5))));
+        assert!(result.is_err());
+    }
+
+    fn timed(record: TransactionInput, timestamp: u64) -> TransactionInput {
+        TransactionInput {
+            timestamp: Some(timestamp),
+            ..record
+        }
     }
 
     #[test]
-    fn deposit_increases_available() {
+    fn authorize_moves_to_held() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
 
         let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.total(), dec!(10));
+        assert_eq!(account.available, dec!(6));
+        assert_eq!(account.held, dec!(4));
+        assert_eq!(account.total().unwrap(), dec!(10));
     }
 
     #[test]
-    fn multiple_deposits_accumulate() {
+    fn authorize_insufficient_funds_is_err() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(3)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4))));
 
+        assert!(matches!(result, Err(PaymentError::InsufficientFunds(ClientId(1), _, _))));
         let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(15));
+        assert_eq!(account.available, dec!(3));
+        assert_eq!(account.held, dec!(0));
     }
 
     #[test]
-    fn duplicate_deposit_tx_id_is_err() {
+    fn authorize_duplicate_id_is_err() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(99))));
+        let result = engine.process(&tx(TransactionType::Authorize, 1, 1, Some(dec!(4))));
 
-        assert!(result.is_err());
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(10));
+        assert!(matches!(result, Err(PaymentError::DuplicateTransaction(TxId(1)))));
     }
 
     #[test]
-    fn withdrawal_decreases_available() {
+    fn full_capture_removes_hold() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Capture, 1, 2, None)).unwrap();
 
         let account = get_account(&engine, 1);
         assert_eq!(account.available, dec!(6));
-        assert_eq!(account.total(), dec!(6));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(6));
+
+        let result = engine.process(&tx(TransactionType::Capture, 1, 2, None));
+        assert!(matches!(result, Err(PaymentError::AuthorizationNotFound(TxId(2)))));
     }
 
     #[test]
-    fn withdrawal_insufficient_funds_is_err() {
+    fn partial_capture_keeps_remainder_held() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(10))));
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Capture, 1, 2, Some(dec!(1.5)))).unwrap();
 
-        assert!(result.is_err());
         let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(5));
+        assert_eq!(account.available, dec!(6));
+        assert_eq!(account.held, dec!(2.5));
+
+        engine.process(&tx(TransactionType::Void, 1, 2, None)).unwrap();
+        let account = get_account(&engine, 1);
+        assert_eq!(account.available, dec!(8.5));
+        assert_eq!(account.held, dec!(0));
     }
 
     #[test]
-    fn withdrawal_exact_balance() {
+    fn capture_more_than_hold_is_err() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(7)))).unwrap();
-        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(7)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Capture, 1, 2, Some(dec!(5))));
 
+        assert!(matches!(result, Err(PaymentError::CaptureExceedsHold(TxId(2), _, _))));
         let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(4));
     }
 
     #[test]
-    fn dispute_moves_to_held() {
+    fn capture_wrong_client_is_err() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 3, Some(dec!(4)))).unwrap();
 
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, dec!(10));
-        assert_eq!(account.total(), dec!(10));
+        assert!(engine.process(&tx(TransactionType::Capture, 2, 3, None)).is_err());
+        assert!(engine.process(&tx(TransactionType::Void, 2, 3, None)).is_err());
+        assert_eq!(get_account(&engine, 1).held, dec!(4));
     }
 
     #[test]
-    fn dispute_nonexistent_tx_is_err() {
+    fn void_releases_hold() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Dispute, 1, 999, None));
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Void, 1, 2, None)).unwrap();
 
-        assert!(result.is_err());
         let account = get_account(&engine, 1);
         assert_eq!(account.available, dec!(10));
         assert_eq!(account.held, dec!(0));
+        assert!(engine.process(&tx(TransactionType::Void, 1, 2, None)).is_err());
     }
 
     #[test]
-    fn dispute_already_disputed_is_err() {
-        let mut engine = PaymentEngine::new();
+    fn hold_expires_after_transaction_count() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            hold_expiry: HoldExpiry::AfterTransactions(2),
+            ..EngineConfig::default()
+        });
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        let result = engine.process(&tx(TransactionType::Dispute, 1, 1, None));
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 3, Some(dec!(1)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 4, Some(dec!(1)))).unwrap();
+        assert_eq!(get_account(&engine, 1).held, dec!(4));
+        assert!(engine.take_expired_holds().is_empty());
 
-        assert!(result.is_err());
+        engine.process(&tx(TransactionType::Deposit, 2, 5, Some(dec!(1)))).unwrap();
         let account = get_account(&engine, 1);
-        assert_eq!(account.held, dec!(10));
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(
+            engine.take_expired_holds(),
+            vec![ExpiredHold {
+                authorization: TxId(2),
+                client: ClientId(1),
+                amount: dec!(4).into(),
+            }]
+        );
+
+        let result = engine.process(&tx(TransactionType::Capture, 1, 2, None));
+        assert!(matches!(result, Err(PaymentError::AuthorizationNotFound(TxId(2)))));
     }
 
     #[test]
-    fn dispute_wrong_client_is_err() {
+    fn hold_expires_after_time_window() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            hold_expiry: HoldExpiry::AfterSeconds(60),
+            ..EngineConfig::default()
+        });
+        engine.process(&timed(tx(TransactionType::Deposit, 1, 1, Some(dec!(10))), 1_000)).unwrap();
+        engine.process(&timed(tx(TransactionType::Authorize, 1, 2, Some(dec!(4))), 1_000)).unwrap();
+        engine.process(&timed(tx(TransactionType::Deposit, 2, 3, Some(dec!(1))), 1_060)).unwrap();
+        assert_eq!(get_account(&engine, 1).held, dec!(4));
+
+        engine.process(&timed(tx(TransactionType::Deposit, 2, 4, Some(dec!(1))), 1_061)).unwrap();
+        assert_eq!(get_account(&engine, 1).held, dec!(0));
+        assert_eq!(engine.take_expired_holds().len(), 1);
+    }
+
+    #[test]
+    fn captured_hold_does_not_expire() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            hold_expiry: HoldExpiry::AfterTransactions(1),
+            ..EngineConfig::default()
+        });
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Capture, 1, 2, None)).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 3, Some(dec!(1)))).unwrap();
+
+        assert!(engine.take_expired_holds().is_empty());
+        assert_eq!(get_account(&engine, 1).available, dec!(7));
+    }
+
+    #[test]
+    fn refund_debits_available_without_locking() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(5)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Dispute, 2, 1, None));
+        engine.process(&tx(TransactionType::Refund, 1, 1, None)).unwrap();
 
-        assert!(result.is_err());
         let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.available, dec!(0));
         assert_eq!(account.held, dec!(0));
+        assert!(!account.locked);
+        assert_eq!(engine.deposits.get(TxId(1)).unwrap().refunded(), dec!(10));
     }
 
     #[test]
-    fn resolve_moves_back_to_available() {
+    fn partial_refunds_accumulate() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(3)))).unwrap();
+        engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(2)))).unwrap();
+
+        assert_eq!(get_account(&engine, 1).available, dec!(5));
+        assert_eq!(engine.deposits.get(TxId(1)).unwrap().refunded(), dec!(5));
+
+        let result = engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(6))));
+        assert!(matches!(result, Err(PaymentError::RefundExceedsDeposit(TxId(1), _, _))));
+
+        engine.process(&tx(TransactionType::Refund, 1, 1, None)).unwrap();
+        let result = engine.process(&tx(TransactionType::Refund, 1, 1, None));
+        assert!(matches!(result, Err(PaymentError::FullyRefunded(TxId(1)))));
+        assert_eq!(get_account(&engine, 1).available, dec!(0));
+    }
+
+    #[test]
+    fn refund_blocked_while_disputed() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
         engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+        let result = engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(5))));
 
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(10));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total(), dec!(10));
+        assert!(matches!(result, Err(PaymentError::AlreadyUnderDispute(TxId(1)))));
+        assert_eq!(engine.deposits.get(TxId(1)).unwrap().refunded(), dec!(0));
     }
 
     #[test]
-    fn resolve_not_disputed_is_err() {
+    fn refund_insufficient_funds_is_err() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Resolve, 1, 1, None));
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(8)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(5))));
 
-        assert!(result.is_err());
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(10));
+        assert!(matches!(result, Err(PaymentError::InsufficientFunds(ClientId(1), _, _))));
+        assert_eq!(get_account(&engine, 1).available, dec!(2));
     }
 
     #[test]
-    fn chargeback_removes_held_and_locks() {
+    fn refund_wrong_client_is_err() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(10)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Refund, 2, 1, None));
+
+        assert!(matches!(result, Err(PaymentError::TransactionNotFound(TxId(1)))));
+    }
+
+    #[test]
+    fn dispute_after_partial_refund_holds_remainder() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(4)))).unwrap();
         engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
 
         let account = get_account(&engine, 1);
         assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(6));
+
+        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+        let account = get_account(&engine, 1);
         assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total(), dec!(0));
+        assert_eq!(account.total().unwrap(), dec!(0));
         assert!(account.locked);
     }
 
     #[test]
-    fn frozen_account_rejects_all() {
+    fn deposit_overflow_is_err() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(Decimal::MAX))).unwrap();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(1))));
 
-        assert!(engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(50)))).is_err());
-        assert!(engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(1)))).is_err());
+        assert!(matches!(result, Err(PaymentError::Overflow(TxId(2)))));
+        assert_eq!(get_account(&engine, 1).available, Decimal::MAX);
+        assert!(!engine.deposits.contains(TxId(2)));
+    }
+
+    #[test]
+    fn dispute_overflow_leaves_deposit_undisputed() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(Decimal::MAX))).unwrap();
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(Decimal::MAX))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 3, Some(Decimal::MAX))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        let result = engine.process(&tx(TransactionType::Dispute, 1, 3, None));
 
+        assert!(matches!(result, Err(PaymentError::Overflow(TxId(3)))));
+        assert!(!engine.deposits.get(TxId(3)).unwrap().disputed());
         let account = get_account(&engine, 1);
-        assert!(account.locked);
+        assert_eq!(account.held, Decimal::MAX);
         assert_eq!(account.available, dec!(0));
-        assert_eq!(account.total(), dec!(0));
     }
 
     #[test]
-    fn multiple_clients_independent() {
-        let mut engine = PaymentEngine::new();
+    fn deposits_past_dispute_horizon_are_evicted() {
+        let mut engine = PaymentEngine::builder().dispute_horizon(100).build();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 2, None)).unwrap();
+        for id in 3..200 {
+            engine.process(&tx(TransactionType::Deposit, 2, id, Some(dec!(1)))).unwrap();
+        }
+
+        let result = engine.process(&tx(TransactionType::Dispute, 1, 1, None));
+        assert!(matches!(result, Err(PaymentError::TransactionNotFound(TxId(1)))));
+        engine.process(&tx(TransactionType::Resolve, 1, 2, None)).unwrap();
+        assert_eq!(get_account(&engine, 1).available, dec!(10));
+        assert_eq!(engine.evicted_deposits(), 62);
+        engine.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn duplicate_replayed_after_dispute_horizon_is_err() {
+        let mut engine = PaymentEngine::builder()
+            .dispute_horizon(10)
+            .duplicates(DuplicatePolicy::Idempotent)
+            .build();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(20)))).unwrap();
-        engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(5)))).unwrap();
+        for id in 2..=200 {
+            engine.process(&tx(TransactionType::Deposit, 2, id, Some(dec!(1)))).unwrap();
+        }
+        assert!(!engine.deposits.contains(TxId(1)));
 
-        let a1 = get_account(&engine, 1);
-        let a2 = get_account(&engine, 2);
-        assert_eq!(a1.available, dec!(5));
-        assert_eq!(a2.available, dec!(20));
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10))));
+        assert!(matches!(result, Err(PaymentError::DuplicateTransaction(TxId(1)))));
+        assert_eq!(get_account(&engine, 1).available, dec!(10));
     }
 
     #[test]
-    fn full_dispute_resolve_lifecycle() {
+    fn sequential_deposits_are_stored_compactly() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(100)))).unwrap();
-        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(50)))).unwrap();
-        engine.process(&tx(TransactionType::Withdrawal, 1, 3, Some(dec!(30)))).unwrap();
+        for id in 1..=100_000 {
+            engine.process(&tx(TransactionType::Deposit, id % 100, id, Some(dec!(1.25)))).unwrap();
+        }
 
-        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(20));
-        assert_eq!(account.held, dec!(100));
-        assert_eq!(account.total(), dec!(120));
+        let memory = engine.memory_usage();
+        assert_eq!(memory.deposits.sparse, 0);
+        assert!(memory.deposits.bytes < 100_000 * 24, "{memory:?}");
+        assert!(memory.total() > memory.deposits.bytes);
+    }
 
-        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(120));
-        assert_eq!(account.held, dec!(0));
-        assert!(!account.locked);
+    #[test]
+    fn amount_above_maximum_is_err() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_amount: Some(dec!(1000)),
+            ..EngineConfig::default()
+        });
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(1000)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(1000.0001))));
+
+        assert!(matches!(result, Err(PaymentError::AmountTooLarge(TxId(2), _, _))));
+        assert_eq!(get_account(&engine, 1).available, dec!(1000));
     }
 
     #[test]
-    fn full_dispute_chargeback_lifecycle() {
+    fn excess_precision_rejected_by_default() {
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(100)))).unwrap();
-        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(40)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(1.00000)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(1.00005))));
+
+        assert!(matches!(result, Err(PaymentError::ExcessPrecision(TxId(2), _))));
+        assert_eq!(get_account(&engine, 1).available, dec!(1));
+    }
+
+    #[test]
+    fn excess_precision_round_half_even() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            precision: PrecisionPolicy::RoundHalfEven,
+            ..EngineConfig::default()
+        });
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(1.00005)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(1.00015)))).unwrap();
+
+        assert_eq!(get_account(&engine, 1).available, dec!(2.0002));
+    }
+
+    #[test]
+    fn excess_precision_truncate() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            precision: PrecisionPolicy::Truncate,
+            ..EngineConfig::default()
+        });
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(1.99999)))).unwrap();
+        let result = engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(0.00009))));
+
+        assert!(matches!(result, Err(PaymentError::InvalidAmount(TxId(2), _))));
+        assert_eq!(get_account(&engine, 1).available, dec!(1.9999));
+    }
+
+    #[test]
+    fn default_config_rejects_excess_precision() {
+        assert_eq!(EngineConfig::default().precision, PrecisionPolicy::Reject);
+        assert_eq!(EngineConfig::from_toml("").unwrap().precision, PrecisionPolicy::Reject);
 
+        let csv_data = "type,client,tx,amount\ndeposit,1,1,1.00005\ndeposit,1,2,2.0\n";
+        let engine = crate::io::process_csv(csv_data.as_bytes()).unwrap();
+        assert_eq!(get_account(&engine, 1).available, dec!(2));
+    }
+
+    #[test]
+    fn precision_policy_from_str() {
+        assert_eq!("reject".parse(), Ok(PrecisionPolicy::Reject));
+        assert_eq!("round".parse(), Ok(PrecisionPolicy::RoundHalfEven));
+        assert_eq!("truncate".parse(), Ok(PrecisionPolicy::Truncate));
+        assert!("ceil".parse::<PrecisionPolicy>().is_err());
+    }
+
+    fn arb_amount() -> impl Strategy<Value = Decimal> {
+        prop_oneof![
+            (any::<i64>(), 0..=8u32).prop_map(|(n, scale)| Decimal::new(n, scale)),
+            (any::<u32>(), any::<u32>(), any::<u32>(), any::<bool>(), 0..=28u32)
+                .prop_map(|(lo, mid, hi, neg, scale)| Decimal::from_parts(lo, mid, hi, neg, scale)),
+            Just(Decimal::MAX),
+            Just(Decimal::MIN),
+        ]
+    }
+
+    fn arb_record() -> impl Strategy<Value = TransactionInput> {
+        let tx_type = prop_oneof![
+            Just(TransactionType::Deposit),
+            Just(TransactionType::Withdrawal),
+            Just(TransactionType::Dispute),
+            Just(TransactionType::Resolve),
+            Just(TransactionType::Chargeback),
+            Just(TransactionType::Authorize),
+            Just(TransactionType::Capture),
+            Just(TransactionType::Void),
+            Just(TransactionType::Refund),
+            Just(TransactionType::Open),
+            Just(TransactionType::Close),
+        ];
+        (tx_type, 0..4u64, 0..16u64, proptest::option::of(arb_amount()))
+            .prop_map(|(tx_type, client, id, amount)| tx(tx_type, client, id, amount))
+    }
+
+    fn arb_config() -> impl Strategy<Value = EngineConfig> {
+        let precision = prop_oneof![
+            Just(PrecisionPolicy::Reject),
+            Just(PrecisionPolicy::RoundHalfEven),
+            Just(PrecisionPolicy::Truncate),
+        ];
+        (precision, proptest::option::of(arb_amount())).prop_map(|(precision, max_amount)| {
+            EngineConfig {
+                precision,
+                max_amount,
+                ..EngineConfig::default()
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn fuzz_arbitrary_amounts_never_panic(
+            config in arb_config(),
+            records in proptest::collection::vec(arb_record(), 0..64),
+        ) {
+            let max_amount = config.max_amount;
+            let mut engine = PaymentEngine::with_config(config);
+            for record in &records {
+                match engine.process(record) {
+                    Err(PaymentError::ExcessPrecision(_, amount)) => {
+                        prop_assert!(amount.normalize().scale() > AMOUNT_SCALE);
+                    }
+                    Err(PaymentError::AmountTooLarge(_, amount, max)) => {
+                        prop_assert!(amount > max);
+                    }
+                    _ => {}
+                }
+            }
+
+            for (_, deposit) in engine.deposits.iter() {
+                prop_assert!(deposit.amount().to_decimal().scale() <= AMOUNT_SCALE);
+                if let Some(max) = max_amount {
+                    prop_assert!(deposit.amount() <= max.into());
+                }
+            }
+            for account in engine.accounts() {
+                prop_assert!(account.available.checked_add(account.held).is_some());
+                prop_assert!(account.held >= Amount::ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn subscriber_receives_events_with_balances() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::new();
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
         engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(-40));
-        assert_eq!(account.held, dec!(100));
 
+        let events: Vec<Event> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].seq, 1);
+        assert_eq!(events[0].kind, EventKind::Deposited { amount: dec!(10) });
+        assert_eq!(events[0].before, Balances::default());
+        assert_eq!(events[0].after.available, dec!(10));
+        assert_eq!(events[1].kind, EventKind::DisputeOpened { amount: dec!(10) });
+        assert_eq!(events[1].before, events[0].after);
+        assert_eq!(events[1].after.held, dec!(10));
+    }
+
+    #[test]
+    fn chargeback_emits_account_locked() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::new();
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
         engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(-40));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.total(), dec!(-40));
-        assert!(account.locked);
+
+        let events: Vec<Event> = receiver.try_iter().collect();
+        let kinds: Vec<&EventKind> = events.iter().map(|e| &e.kind).collect();
+        assert_eq!(
+            kinds[2..],
+            [&EventKind::ChargedBack { amount: dec!(10) }, &EventKind::AccountLocked]
+        );
+        assert!(!events[2].after.locked);
+        assert_eq!(events[3].before, events[2].after);
+        assert!(events[3].after.locked);
+        assert_eq!(events[3].seq, 3);
     }
 
     #[test]
-    fn re_dispute_after_resolve() {
+    fn rejection_emits_rejected_event() {
+        let (sender, receiver) = mpsc::channel();
         let mut engine = PaymentEngine::new();
-        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(50)))).unwrap();
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+        assert!(engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(10)))).is_err());
+
+        let event = receiver.try_iter().last().unwrap();
+        assert!(matches!(
+            event.kind,
+            EventKind::Rejected {
+                tx_type: TransactionType::Withdrawal,
+                ..
+            }
+        ));
+        assert_eq!(event.before, event.after);
+        assert_eq!(event.after.available, dec!(5));
+    }
+
+    #[test]
+    fn hold_expiry_emits_event() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            hold_expiry: HoldExpiry::AfterTransactions(0),
+            ..EngineConfig::default()
+        });
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(4)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 3, Some(dec!(1)))).unwrap();
+
+        let events: Vec<Event> = receiver.try_iter().collect();
+        let expired = &events[2];
+        assert_eq!(expired.kind, EventKind::HoldExpired { amount: dec!(4) });
+        assert_eq!(expired.client, ClientId(1));
+        assert_eq!(expired.tx, TxId(2));
+        assert_eq!(expired.after.available, dec!(10));
+    }
+
+    #[test]
+    fn rebuild_from_events_matches_engine() {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::new();
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 2, 2, Some(dec!(20)))).unwrap();
+        engine.process(&tx(TransactionType::Refund, 1, 1, Some(dec!(3)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 2, 3, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Capture, 2, 3, Some(dec!(2)))).unwrap();
         engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        engine.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
 
+        let events: Vec<Event> = receiver.try_iter().collect();
+        let rebuilt = PaymentEngine::from_events(&events).unwrap();
+        assert_same_state(&engine, &rebuilt);
+
+        let mut rebuilt = rebuilt;
+        rebuilt.process(&tx(TransactionType::Resolve, 1, 1, None)).unwrap();
+        rebuilt.process(&tx(TransactionType::Capture, 2, 3, None)).unwrap();
+        assert_eq!(get_account(&rebuilt, 1).available, dec!(7));
+        assert_eq!(get_account(&rebuilt, 2).held, dec!(0));
+    }
+
+    fn assert_same_state(engine: &PaymentEngine, rebuilt: &PaymentEngine) {
+        for account in engine.accounts() {
+            match rebuilt.accounts.get(&account.client) {
+                Some(other) => {
+                    assert_eq!(Balances::of(account), Balances::of(other));
+                }
+                // Rejected transactions can leave empty accounts behind, and those
+                // never show up in the event log.
+                None => assert_eq!(Balances::of(account), Balances::default()),
+            }
+        }
+        assert_eq!(engine.deposits, rebuilt.deposits);
+        assert_eq!(engine.authorizations, rebuilt.authorizations);
+        assert_eq!(engine.seq, rebuilt.seq);
+    }
+
+    proptest! {
+        #[test]
+        fn rebuild_from_random_event_log(
+            records in proptest::collection::vec(arb_record(), 0..64),
+        ) {
+            let (sender, receiver) = mpsc::channel();
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                precision: PrecisionPolicy::Truncate,
+                ..EngineConfig::default()
+            });
+            engine.subscribe(sender);
+            for record in &records {
+                let _ = engine.process(record);
+            }
+
+            let events: Vec<Event> = receiver.try_iter().collect();
+            prop_assert_eq!(events.len(), records.len() + events.iter().filter(|e| {
+                matches!(e.kind, EventKind::AccountLocked)
+            }).count());
+            assert_same_state(&engine, &PaymentEngine::from_events(&events).unwrap());
+        }
+    }
+
+    #[test]
+    fn check_invariants_detects_held_without_dispute() {
+        let mut engine = PaymentEngine::new();
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
         engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
-        let account = get_account(&engine, 1);
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, dec!(50));
+        assert_eq!(engine.check_invariants(), Ok(()));
+
+        engine.deposits.set_disputed(TxId(1), false);
+        assert_eq!(
+            engine.check_invariants(),
+            Err(InvariantViolation::HeldMismatch(ClientId(1), dec!(10), dec!(0)))
+        );
     }
 
     #[test]
-    fn deposit_missing_amount_is_err() {
+    fn check_invariants_detects_changed_locked_account() {
         let mut engine = PaymentEngine::new();
-        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, None));
-        assert!(result.is_err());
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Deposit, 1, 2, Some(dec!(5)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 2, None)).unwrap();
+        engine.process(&tx(TransactionType::Chargeback, 1, 2, None)).unwrap();
+        assert_eq!(engine.check_invariants(), Ok(()));
+
+        engine.accounts.get_mut(&ClientId(1)).unwrap().available = dec!(9).into();
+        assert_eq!(
+            engine.check_invariants(),
+            Err(InvariantViolation::LockedAccountChanged(ClientId(1)))
+        );
     }
 
     #[test]
-    fn withdrawal_missing_amount_is_err() {
+    fn check_invariants_detects_money_from_nowhere() {
         let mut engine = PaymentEngine::new();
         engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
-        let result = engine.process(&tx(TransactionType::Withdrawal, 1, 2, None));
-        assert!(result.is_err());
+        engine.process(&tx(TransactionType::Withdrawal, 1, 2, Some(dec!(4)))).unwrap();
+        assert_eq!(engine.check_invariants(), Ok(()));
+
+        engine.accounts.get_mut(&ClientId(1)).unwrap().available = dec!(7).into();
+        assert_eq!(
+            engine.check_invariants(),
+            Err(InvariantViolation::MoneyNotConserved(dec!(7), dec!(6)))
+        );
     }
 
     #[test]
-    fn deposit_zero_amount_is_err() {
+    fn rebuilt_engine_passes_invariants() {
+        let (sender, receiver) = mpsc::channel();
         let mut engine = PaymentEngine::new();
-        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(0))));
-        assert!(result.is_err());
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        engine.process(&tx(TransactionType::Authorize, 1, 2, Some(dec!(3)))).unwrap();
+        engine.process(&tx(TransactionType::Capture, 1, 2, Some(dec!(1)))).unwrap();
+        engine.process(&tx(TransactionType::Dispute, 1, 1, None)).unwrap();
+        engine.process(&tx(TransactionType::Chargeback, 1, 1, None)).unwrap();
+
+        let events: Vec<Event> = receiver.try_iter().collect();
+        assert_eq!(PaymentEngine::from_events(&events).unwrap().check_invariants(), Ok(()));
     }
 
     #[test]
-    fn deposit_negative_amount_is_err() {
+    fn tampered_event_log_is_rejected_instead_of_panicking() {
+        let (sender, receiver) = mpsc::channel();
         let mut engine = PaymentEngine::new();
-        let result = engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(-5))));
-        assert!(result.is_err());
+        engine.subscribe(sender);
+        engine.process(&tx(TransactionType::Deposit, 1, 1, Some(dec!(10)))).unwrap();
+        let events: Vec<Event> = receiver.try_iter().collect();
+
+        let mut duplicate = events[0].clone();
+        duplicate.before = duplicate.after;
+        assert!(matches!(
+            PaymentEngine::from_events([&events[0], &duplicate]),
+            Err(PaymentError::DuplicateTransaction(TxId(1)))
+        ));
+
+        let mut gap = events[0].clone();
+        gap.seq = 2;
+        assert!(matches!(
+            PaymentEngine::from_events([&events[0], &gap]),
+            Err(PaymentError::EventMismatch(2, ClientId(1)))
+        ));
+
+        let mut inflated = events[0].clone();
+        inflated.after.available = dec!(1000);
+        let rebuilt = PaymentEngine::from_events([&inflated]).unwrap();
+        assert_eq!(
+            rebuilt.check_invariants(),
+            Err(InvariantViolation::MoneyNotConserved(dec!(1000), dec!(10)))
+        );
+    }
+
+    /// Mostly well-formed records over a small id space, so disputes, refunds
+    /// and captures usually refer to something that exists.
+    fn arb_plausible_record() -> impl Strategy<Value = TransactionInput> {
+        let tx_type = prop_oneof![
+            4 => Just(TransactionType::Deposit),
+            3 => Just(TransactionType::Withdrawal),
+            2 => Just(TransactionType::Dispute),
+            1 => Just(TransactionType::Resolve),
+            1 => Just(TransactionType::Chargeback),
+            2 => Just(TransactionType::Authorize),
+            1 => Just(TransactionType::Capture),
+            1 => Just(TransactionType::Void),
+            1 => Just(TransactionType::Refund),
+            1 => Just(TransactionType::Close),
+        ];
+        let amount = (1..100_000i64).prop_map(|cents| Decimal::new(cents, 2));
+        (tx_type, 1..4u64, 1..12u64, proptest::option::weighted(0.8, amount))
+            .prop_map(|(tx_type, client, id, amount)| tx(tx_type, client, id, amount))
+    }
+
+    fn arb_sequence() -> impl Strategy<Value = Vec<TransactionInput>> {
+        let record = prop_oneof![4 => arb_plausible_record(), 1 => arb_record()];
+        proptest::collection::vec(record, 0..96)
+    }
+
+    fn arb_invariant_config() -> impl Strategy<Value = EngineConfig> {
+        use crate::risk::{RiskCheck, RiskRule, Window};
+
+        let hold_expiry = prop_oneof![
+            Just(HoldExpiry::Never),
+            (0..4u64).prop_map(HoldExpiry::AfterTransactions),
+        ];
+        let risk = proptest::option::of(1..4usize).prop_map(|count| RiskConfig {
+            checks: count
+                .map(|count| RiskCheck {
+                    rule: RiskRule::MaxWithdrawals {
+                        count,
+                        window: Window::Transactions(8),
+                    },
+                    action: RiskAction::Freeze,
+                })
+                .into_iter()
+                .collect(),
+        });
+        (arb_config(), hold_expiry, risk).prop_map(|(config, hold_expiry, risk)| EngineConfig {
+            hold_expiry,
+            risk,
+            ..config
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn invariants_hold_after_every_transaction(
+            config in arb_invariant_config(),
+            records in arb_sequence(),
+        ) {
+            let mut engine = PaymentEngine::with_config(config);
+            let mut locked: HashMap<ClientId, Balances> = HashMap::new();
+            for record in &records {
+                let _ = engine.process(record);
+                prop_assert_eq!(engine.check_invariants(), Ok(()));
+
+                for account in engine.accounts().filter(|account| account.locked) {
+                    let snapshot = *locked.entry(account.client).or_insert(Balances::of(account));
+                    prop_assert_eq!(snapshot, Balances::of(account));
+                }
+            }
+        }
     }
 }
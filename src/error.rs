@@ -1,35 +1,183 @@
 use rust_decimal::Decimal;
 use thiserror::Error;
 
+use crate::hooks::HookError;
+use crate::transaction::{ClientId, Provenance, TransactionType, TxId};
+
 #[derive(Debug, Error)]
 pub enum PaymentError {
     #[error("account {0} is frozen")]
-    AccountFrozen(u16),
+    AccountFrozen(ClientId),
+
+    #[error("account {0} is closed")]
+    AccountClosed(ClientId),
+
+    #[error("account {0} already exists")]
+    AccountExists(ClientId),
+
+    #[error("account {0} not found")]
+    AccountNotFound(ClientId),
+
+    #[error("account {0} cannot be closed with available {1} and held {2}")]
+    AccountNotEmpty(ClientId, Decimal, Decimal),
+
+    #[error("{1} transactions are disabled (transaction {0})")]
+    TypeDisabled(TxId, TransactionType),
 
     #[error("missing amount for transaction {0}")]
-    MissingAmount(u32),
+    MissingAmount(TxId),
 
     #[error("duplicate transaction id {0}")]
-    DuplicateTransaction(u32),
+    DuplicateTransaction(TxId),
 
     #[error("invalid amount {1} for transaction {0}")]
-    InvalidAmount(u32, Decimal),
+    InvalidAmount(TxId, Decimal),
+
+    #[error("amount {1} for transaction {0} has more than 4 decimal places")]
+    ExcessPrecision(TxId, Decimal),
+
+    #[error("amount {1} for transaction {0} exceeds the maximum of {2}")]
+    AmountTooLarge(TxId, Decimal, Decimal),
+
+    #[error("arithmetic overflow while applying transaction {0}")]
+    Overflow(TxId),
+
+    #[error("balances of account {0} overflow")]
+    BalanceOverflow(ClientId),
 
     #[error("insufficient funds for client {0}: need {1}, have {2}")]
-    InsufficientFunds(u16, Decimal, Decimal),
+    InsufficientFunds(ClientId, Decimal, Decimal),
 
     #[error("transaction {0} not found")]
-    TransactionNotFound(u32),
+    TransactionNotFound(TxId),
 
     #[error("transaction {0} is already under dispute")]
-    AlreadyUnderDispute(u32),
+    AlreadyUnderDispute(TxId),
 
     #[error("transaction {0} is not under dispute")]
-    NotUnderDispute(u32),
+    NotUnderDispute(TxId),
+
+    #[error("refund of {1} exceeds unrefunded amount {2} for transaction {0}")]
+    RefundExceedsDeposit(TxId, Decimal, Decimal),
+
+    #[error("transaction {0} is already fully refunded")]
+    FullyRefunded(TxId),
+
+    #[error("authorization {0} not found")]
+    AuthorizationNotFound(TxId),
+
+    #[error("capture of {1} exceeds remaining hold {2} for authorization {0}")]
+    CaptureExceedsHold(TxId, Decimal, Decimal),
+
+    #[error("transaction {0} rejected by risk rules")]
+    RiskRejected(TxId),
+
+    #[error("account {0} frozen by risk rules at transaction {1}")]
+    RiskFrozen(ClientId, TxId),
+
+    #[error("transaction {0} vetoed: {1}")]
+    Vetoed(TxId, HookError),
+
+    /// An error for a record that carries its input position.
+    #[error("{0}: {1}")]
+    At(Provenance, Box<PaymentError>),
+
+    #[error("transaction {0} rolled back with its batch")]
+    BatchRolledBack(TxId),
+
+    #[error("event {0} does not start from the balances of account {1}")]
+    EventMismatch(u64, ClientId),
+
+    #[error("audit log broken at entry {0}: {1}")]
+    AuditChainBroken(u64, String),
 
     #[error("csv error: {0}")]
     Csv(#[from] csv::Error),
 
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+/// A broken bookkeeping rule found by `PaymentEngine::check_invariants`.
+#[derive(Debug, Error, PartialEq)]
+pub enum InvariantViolation {
+    #[error("account {0} has negative held balance {1}")]
+    NegativeHeld(ClientId, Decimal),
+
+    #[error("total for account {0} is not representable")]
+    TotalOverflow(ClientId),
+
+    #[error("closed account {0} has a non-zero balance")]
+    ClosedWithBalance(ClientId),
+
+    #[error("account {0} holds {1} but its open disputes and authorizations add up to {2}")]
+    HeldMismatch(ClientId, Decimal, Decimal),
+
+    #[error("open disputes and authorizations for account {0} overflow")]
+    HeldOverflow(ClientId),
+
+    #[error("locked account {0} changed after it was locked")]
+    LockedAccountChanged(ClientId),
+
+    #[error("accounts hold {0} in total but net inflows are {1}")]
+    MoneyNotConserved(Decimal, Decimal),
+}
+
+impl PaymentError {
+    /// Stable snake_case name of the variant, used as a metrics label. `At`
+    /// reports the kind of the error it wraps.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::At(_, error) => error.kind(),
+            Self::AccountFrozen(..) => "account_frozen",
+            Self::AccountClosed(..) => "account_closed",
+            Self::AccountExists(..) => "account_exists",
+            Self::AccountNotFound(..) => "account_not_found",
+            Self::AccountNotEmpty(..) => "account_not_empty",
+            Self::TypeDisabled(..) => "type_disabled",
+            Self::MissingAmount(..) => "missing_amount",
+            Self::DuplicateTransaction(..) => "duplicate_transaction",
+            Self::InvalidAmount(..) => "invalid_amount",
+            Self::ExcessPrecision(..) => "excess_precision",
+            Self::AmountTooLarge(..) => "amount_too_large",
+            Self::Overflow(..) => "overflow",
+            Self::BalanceOverflow(..) => "balance_overflow",
+            Self::InsufficientFunds(..) => "insufficient_funds",
+            Self::TransactionNotFound(..) => "transaction_not_found",
+            Self::AlreadyUnderDispute(..) => "already_under_dispute",
+            Self::NotUnderDispute(..) => "not_under_dispute",
+            Self::RefundExceedsDeposit(..) => "refund_exceeds_deposit",
+            Self::FullyRefunded(..) => "fully_refunded",
+            Self::AuthorizationNotFound(..) => "authorization_not_found",
+            Self::CaptureExceedsHold(..) => "capture_exceeds_hold",
+            Self::RiskRejected(..) => "risk_rejected",
+            Self::RiskFrozen(..) => "risk_frozen",
+            Self::Vetoed(..) => "vetoed",
+            Self::BatchRolledBack(..) => "batch_rolled_back",
+            Self::EventMismatch(..) => "event_mismatch",
+            Self::AuditChainBroken(..) => "audit_chain_broken",
+            Self::Csv(..) => "csv",
+            Self::Json(..) => "json",
+            Self::Io(..) => "io",
+        }
+    }
+
+    /// Input position of the record that failed, if it was read from a file.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        match self {
+            Self::At(provenance, _) => Some(provenance),
+            _ => None,
+        }
+    }
+
+    /// The error without its `At` wrapper.
+    pub fn inner(&self) -> &PaymentError {
+        match self {
+            Self::At(_, error) => error,
+            error => error,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::mpsc::Sender;
+
+use rust_decimal::Decimal;
+use rust_decimal::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::transaction::{ClientId, Provenance, TransactionType, TxId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+impl Balances {
+    pub fn of(account: &Account) -> Self {
+        Self {
+            available: account.available.into(),
+            held: account.held.into(),
+            locked: account.locked,
+        }
+    }
+
+    /// `None` if `available + held` overflows, which only a crafted event
+    /// log can cause.
+    pub fn total(&self) -> Option<Decimal> {
+        self.available.checked_add(self.held)
+    }
+}
+
+impl Default for Balances {
+    fn default() -> Self {
+        Self {
+            available: dec!(0),
+            held: dec!(0),
+            locked: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    Deposited {
+        amount: Decimal,
+    },
+    Withdrawn {
+        amount: Decimal,
+    },
+    DisputeOpened {
+        amount: Decimal,
+    },
+    DisputeResolved {
+        amount: Decimal,
+    },
+    ChargedBack {
+        amount: Decimal,
+    },
+    AccountLocked,
+    AccountOpened {
+        metadata: Option<String>,
+    },
+    AccountClosed {
+        metadata: Option<String>,
+    },
+    Authorized {
+        amount: Decimal,
+        expires_at: Option<u64>,
+    },
+    Captured {
+        amount: Decimal,
+    },
+    Voided {
+        amount: Decimal,
+    },
+    HoldExpired {
+        amount: Decimal,
+    },
+    Refunded {
+        amount: Decimal,
+    },
+    Rejected {
+        tx_type: TransactionType,
+        reason: String,
+    },
+    /// An identical resubmission ignored under `DuplicatePolicy::Idempotent`.
+    Replayed {
+        tx_type: TransactionType,
+    },
+}
+
+impl EventKind {
+    /// The serialized `type` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Deposited { .. } => "deposited",
+            Self::Withdrawn { .. } => "withdrawn",
+            Self::DisputeOpened { .. } => "dispute_opened",
+            Self::DisputeResolved { .. } => "dispute_resolved",
+            Self::ChargedBack { .. } => "charged_back",
+            Self::AccountLocked => "account_locked",
+            Self::AccountOpened { .. } => "account_opened",
+            Self::AccountClosed { .. } => "account_closed",
+            Self::Authorized { .. } => "authorized",
+            Self::Captured { .. } => "captured",
+            Self::Voided { .. } => "voided",
+            Self::HoldExpired { .. } => "hold_expired",
+            Self::Refunded { .. } => "refunded",
+            Self::Rejected { .. } => "rejected",
+            Self::Replayed { .. } => "replayed",
+        }
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            Self::Deposited { amount }
+            | Self::Withdrawn { amount }
+            | Self::DisputeOpened { amount }
+            | Self::DisputeResolved { amount }
+            | Self::ChargedBack { amount }
+            | Self::Authorized { amount, .. }
+            | Self::Captured { amount }
+            | Self::Voided { amount }
+            | Self::HoldExpired { amount }
+            | Self::Refunded { amount } => Some(*amount),
+            Self::AccountLocked
+            | Self::AccountOpened { .. }
+            | Self::AccountClosed { .. }
+            | Self::Rejected { .. }
+            | Self::Replayed { .. } => None,
+        }
+    }
+}
+
+/// A single state change, numbered by the `seq` of the transaction that caused it.
+///
+/// `Rejected` and `Replayed` events leave the account untouched, so
+/// `before == after`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub seq: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(flatten)]
+    pub kind: EventKind,
+    pub before: Balances,
+    pub after: Balances,
+    /// Input line of the transaction that caused the event, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+pub trait EventSubscriber: Send {
+    fn on_event(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event) + Send> EventSubscriber for F {
+    fn on_event(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+impl EventSubscriber for Sender<Event> {
+    fn on_event(&mut self, event: &Event) {
+        // A dropped receiver just means nobody is listening any more.
+        let _ = self.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_round_trips_through_json() {
+        let event = Event {
+            seq: 3,
+            client: ClientId(1),
+            tx: TxId(7),
+            kind: EventKind::DisputeOpened { amount: dec!(2.5) },
+            before: Balances {
+                available: dec!(2.5),
+                held: dec!(0),
+                locked: false,
+            },
+            after: Balances {
+                available: dec!(0),
+                held: dec!(2.5),
+                locked: false,
+            },
+            provenance: None,
+        };
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""type":"dispute_opened""#), "{json}");
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn rejected_event_round_trips_through_json() {
+        let event = Event {
+            seq: 1,
+            client: ClientId(2),
+            tx: TxId(9),
+            kind: EventKind::Rejected {
+                tx_type: TransactionType::Withdrawal,
+                reason: "insufficient funds".to_string(),
+            },
+            before: Balances::default(),
+            after: Balances::default(),
+            provenance: Some(Provenance {
+                file: Some("input.csv".into()),
+                line: 4,
+                byte: 61,
+            }),
+        };
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(
+            json.contains(r#""provenance":{"file":"input.csv","line":4,"byte":61}"#),
+            "{json}"
+        );
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+}
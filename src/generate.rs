@@ -0,0 +1,644 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use rust_decimal::dec;
+use rust_decimal::prelude::FromPrimitive;
+
+use crate::account::Account;
+use crate::amount::Amount;
+use crate::transaction::{ClientId, TransactionInput, TransactionType, TxId};
+
+/// SplitMix64. Hand-rolled so a seed reproduces the same file regardless of
+/// dependency upgrades.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; `n` must be non-zero.
+    fn below(&mut self, n: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(n)) >> 64) as u64
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.unit() < p
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> Option<T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(items[self.below(items.len() as u64) as usize])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountDistribution {
+    Uniform { min: Decimal, max: Decimal },
+    LogNormal { median: Decimal, sigma: f64 },
+}
+
+impl Default for AmountDistribution {
+    fn default() -> Self {
+        Self::Uniform {
+            min: dec!(1),
+            max: dec!(10000),
+        }
+    }
+}
+
+impl FromStr for AmountDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid amount distribution `{s}` (expected uniform:MIN:MAX or lognormal:MEDIAN:SIGMA)"
+            )
+        };
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            ["uniform", min, max] => {
+                let min: Decimal = min.parse().map_err(|_| invalid())?;
+                let max: Decimal = max.parse().map_err(|_| invalid())?;
+                if min <= dec!(0) || max < min {
+                    return Err(invalid());
+                }
+                Ok(Self::Uniform { min, max })
+            }
+            ["lognormal", median, sigma] => {
+                let median: Decimal = median.parse().map_err(|_| invalid())?;
+                let sigma: f64 = sigma.parse().map_err(|_| invalid())?;
+                if median <= dec!(0) || !(sigma >= 0.0 && sigma.is_finite()) {
+                    return Err(invalid());
+                }
+                Ok(Self::LogNormal { median, sigma })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Relative weights of the transaction types that start something new.
+/// Disputes, resolves, chargebacks, captures and voids are follow-ups of
+/// earlier deposits and authorizations and are driven by the rates in
+/// `GeneratorConfig` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeMix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub authorize: u32,
+    pub refund: u32,
+}
+
+impl Default for TypeMix {
+    fn default() -> Self {
+        Self {
+            deposit: 75,
+            withdrawal: 25,
+            authorize: 0,
+            refund: 0,
+        }
+    }
+}
+
+impl FromStr for TypeMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = Self {
+            deposit: 0,
+            withdrawal: 0,
+            authorize: 0,
+            refund: 0,
+        };
+        for part in s.split(',') {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid mix entry `{part}` (expected TYPE=WEIGHT)"))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight in mix entry `{part}`"))?;
+            match name.trim() {
+                "deposit" => mix.deposit = weight,
+                "withdrawal" => mix.withdrawal = weight,
+                "authorize" => mix.authorize = weight,
+                "refund" => mix.refund = weight,
+                other => {
+                    return Err(format!(
+                        "unknown mix type `{other}` (expected deposit, withdrawal, authorize or refund)"
+                    ));
+                }
+            }
+        }
+        if mix.deposit == 0 {
+            return Err("the mix needs a non-zero deposit weight".to_string());
+        }
+        Ok(mix)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub clients: u64,
+    pub transactions: u64,
+    pub mix: TypeMix,
+    /// Share of deposits that are disputed later on.
+    pub dispute_rate: f64,
+    /// Share of disputes that end in a chargeback rather than a resolve.
+    pub chargeback_rate: f64,
+    /// Share of rows the engine is expected to reject.
+    pub invalid_rate: f64,
+    pub amounts: AmountDistribution,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            clients: 1000,
+            transactions: 10_000,
+            mix: TypeMix::default(),
+            dispute_rate: 0.05,
+            chargeback_rate: 0.3,
+            invalid_rate: 0.0,
+            amounts: AmountDistribution::default(),
+        }
+    }
+}
+
+/// Share of rows spent on pending disputes, resolutions, captures and voids
+/// when any are queued.
+const FOLLOW_UP_SHARE: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy)]
+struct ModelDeposit {
+    client: ClientId,
+    amount: Amount,
+    refunded: Amount,
+    disputed: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FollowUp {
+    Dispute(TxId),
+    Resolve(TxId),
+    Chargeback(TxId),
+    Capture(TxId),
+    Void(TxId),
+}
+
+/// Produces a reproducible transaction stream while tracking the balances the
+/// engine should end up with under its default settings.
+///
+/// The model is written independently of `PaymentEngine`: every valid row is
+/// one the model knows will apply, and every invalid row is one it knows will
+/// be rejected, so `expected_accounts` can serve as a golden file.
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: Rng,
+    accounts: BTreeMap<ClientId, Account>,
+    deposits: HashMap<TxId, ModelDeposit>,
+    deposit_ids: Vec<TxId>,
+    authorizations: HashMap<TxId, (ClientId, Amount)>,
+    follow_ups: Vec<FollowUp>,
+    next_id: u64,
+    emitted: u64,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        Self {
+            rng: Rng(config.seed),
+            config,
+            accounts: BTreeMap::new(),
+            deposits: HashMap::new(),
+            deposit_ids: Vec::new(),
+            authorizations: HashMap::new(),
+            follow_ups: Vec::new(),
+            next_id: 1,
+            emitted: 0,
+        }
+    }
+
+    /// Final account states, ordered by client. Complete once the iterator is
+    /// exhausted.
+    pub fn expected_accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    fn fresh_id(&mut self) -> TxId {
+        let id = TxId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn client(&mut self) -> ClientId {
+        ClientId(1 + self.rng.below(self.config.clients.max(1)))
+    }
+
+    fn amount(&mut self) -> Amount {
+        let unit = dec!(0.0001);
+        let amount = match self.config.amounts {
+            AmountDistribution::Uniform { min, max } => {
+                let steps = ((max - min) / unit).trunc();
+                let steps = u64::try_from(steps.mantissa()).unwrap_or(u64::MAX);
+                min + Decimal::from(self.rng.below(steps.saturating_add(1))) * unit
+            }
+            AmountDistribution::LogNormal { median, sigma } => {
+                // Box-Muller; 1 - unit() keeps the logarithm finite.
+                let radius = (-2.0 * (1.0 - self.rng.unit()).ln()).sqrt();
+                let z = radius * (std::f64::consts::TAU * self.rng.unit()).cos();
+                let factor = Decimal::from_f64((sigma * z).exp()).unwrap_or(Decimal::ONE);
+                median.checked_mul(factor).unwrap_or(median)
+            }
+        };
+        Amount::from(amount.max(unit))
+    }
+
+    fn usable(&self, client: ClientId) -> Option<&Account> {
+        self.accounts.get(&client).filter(|account| !account.locked)
+    }
+
+    fn record(
+        tx_type: TransactionType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Amount>,
+    ) -> TransactionInput {
+        TransactionInput {
+            tx_type,
+            client,
+            tx,
+            amount: amount.map(Decimal::from),
+            timestamp: None,
+            metadata: None,
+            provenance: None,
+        }
+    }
+
+    fn deposit(&mut self) -> TransactionInput {
+        let client = self.client();
+        let tx = self.fresh_id();
+        let amount = self.amount();
+        let adjusted = match self.accounts.get(&client) {
+            Some(account) if account.locked => None,
+            Some(account) => account.checked_adjust(amount, Amount::ZERO),
+            None => Some((amount, Amount::ZERO)),
+        };
+        if let Some((available, _)) = adjusted {
+            self.accounts
+                .entry(client)
+                .or_insert_with(|| Account::new(client))
+                .available = available;
+            self.deposits.insert(
+                tx,
+                ModelDeposit {
+                    client,
+                    amount,
+                    refunded: Amount::ZERO,
+                    disputed: false,
+                },
+            );
+            self.deposit_ids.push(tx);
+            if self.rng.chance(self.config.dispute_rate) {
+                self.follow_ups.push(FollowUp::Dispute(tx));
+            }
+        }
+        Self::record(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    fn withdrawal(&mut self) -> Option<TransactionInput> {
+        let client = self.client();
+        let available = self.usable(client)?.available;
+        let amount = self.amount().min(available);
+        if amount <= Amount::ZERO {
+            return None;
+        }
+        let account = self.accounts.get_mut(&client)?;
+        (account.available, _) = account.checked_adjust(-amount, Amount::ZERO)?;
+        let tx = self.fresh_id();
+        Some(Self::record(
+            TransactionType::Withdrawal,
+            client,
+            tx,
+            Some(amount),
+        ))
+    }
+
+    fn authorize(&mut self) -> Option<TransactionInput> {
+        let client = self.client();
+        let available = self.usable(client)?.available;
+        let amount = self.amount().min(available);
+        if amount <= Amount::ZERO {
+            return None;
+        }
+        let account = self.accounts.get_mut(&client)?;
+        (account.available, account.held) = account.checked_adjust(-amount, amount)?;
+        let tx = self.fresh_id();
+        self.authorizations.insert(tx, (client, amount));
+        let follow_up = if self.rng.chance(0.5) {
+            FollowUp::Capture(tx)
+        } else {
+            FollowUp::Void(tx)
+        };
+        self.follow_ups.push(follow_up);
+        Some(Self::record(
+            TransactionType::Authorize,
+            client,
+            tx,
+            Some(amount),
+        ))
+    }
+
+    fn refund(&mut self) -> Option<TransactionInput> {
+        let tx = self.rng.pick(&self.deposit_ids)?;
+        let deposit = *self.deposits.get(&tx)?;
+        let available = self.usable(deposit.client)?.available;
+        let outstanding = deposit.amount.checked_sub(deposit.refunded)?;
+        if deposit.disputed || outstanding.is_zero() {
+            return None;
+        }
+        let amount = self.amount().min(outstanding).min(available);
+        if amount <= Amount::ZERO {
+            return None;
+        }
+        let refunded = deposit.refunded.checked_add(amount)?;
+        let account = self.accounts.get_mut(&deposit.client)?;
+        (account.available, _) = account.checked_adjust(-amount, Amount::ZERO)?;
+        self.deposits.get_mut(&tx)?.refunded = refunded;
+        Some(Self::record(
+            TransactionType::Refund,
+            deposit.client,
+            tx,
+            Some(amount),
+        ))
+    }
+
+    fn follow_up(&mut self) -> Option<TransactionInput> {
+        if self.follow_ups.is_empty() {
+            return None;
+        }
+        let index = self.rng.below(self.follow_ups.len() as u64) as usize;
+        match self.follow_ups.swap_remove(index) {
+            FollowUp::Dispute(tx) => {
+                let deposit = *self.deposits.get(&tx)?;
+                self.usable(deposit.client)?;
+                let outstanding = deposit.amount.checked_sub(deposit.refunded)?;
+                if deposit.disputed || outstanding.is_zero() {
+                    return None;
+                }
+                let account = self.accounts.get_mut(&deposit.client)?;
+                (account.available, account.held) =
+                    account.checked_adjust(-outstanding, outstanding)?;
+                self.deposits.get_mut(&tx)?.disputed = true;
+                let settle = if self.rng.chance(self.config.chargeback_rate) {
+                    FollowUp::Chargeback(tx)
+                } else {
+                    FollowUp::Resolve(tx)
+                };
+                self.follow_ups.push(settle);
+                Some(Self::record(
+                    TransactionType::Dispute,
+                    deposit.client,
+                    tx,
+                    None,
+                ))
+            }
+            follow_up @ (FollowUp::Resolve(tx) | FollowUp::Chargeback(tx)) => {
+                let deposit = *self.deposits.get(&tx)?;
+                self.usable(deposit.client)?;
+                let outstanding = deposit.amount.checked_sub(deposit.refunded)?;
+                let account = self.accounts.get_mut(&deposit.client)?;
+                let tx_type = if matches!(follow_up, FollowUp::Chargeback(_)) {
+                    (_, account.held) = account.checked_adjust(Amount::ZERO, -outstanding)?;
+                    account.locked = true;
+                    TransactionType::Chargeback
+                } else {
+                    (account.available, account.held) =
+                        account.checked_adjust(outstanding, -outstanding)?;
+                    TransactionType::Resolve
+                };
+                self.deposits.get_mut(&tx)?.disputed = false;
+                Some(Self::record(tx_type, deposit.client, tx, None))
+            }
+            follow_up @ (FollowUp::Capture(tx) | FollowUp::Void(tx)) => {
+                let (client, amount) = *self.authorizations.get(&tx)?;
+                self.usable(client)?;
+                let account = self.accounts.get_mut(&client)?;
+                let tx_type = if matches!(follow_up, FollowUp::Capture(_)) {
+                    (_, account.held) = account.checked_adjust(Amount::ZERO, -amount)?;
+                    TransactionType::Capture
+                } else {
+                    (account.available, account.held) = account.checked_adjust(amount, -amount)?;
+                    TransactionType::Void
+                };
+                self.authorizations.remove(&tx);
+                Some(Self::record(tx_type, client, tx, None))
+            }
+        }
+    }
+
+    /// A row the engine must reject. Leaves the model untouched.
+    fn invalid(&mut self) -> TransactionInput {
+        let client = self.client();
+        match self.rng.below(5) {
+            0 => {
+                let tx = self.fresh_id();
+                let amount = -self.amount();
+                Self::record(TransactionType::Deposit, client, tx, Some(amount))
+            }
+            1 => {
+                let tx = self.fresh_id();
+                let mut record = Self::record(TransactionType::Deposit, client, tx, None);
+                record.amount = Some(self.amount().to_decimal() + dec!(0.00001));
+                record
+            }
+            2 if !self.deposit_ids.is_empty() => {
+                let tx = self.rng.pick(&self.deposit_ids).expect("checked non-empty");
+                let amount = self.amount();
+                Self::record(TransactionType::Deposit, client, tx, Some(amount))
+            }
+            3 if self.accounts.contains_key(&client) => {
+                let available = self.accounts[&client].available.max(Amount::ZERO);
+                // A negative amount is just as invalid if the overdraft would overflow.
+                let extra = self.amount();
+                let amount = available.checked_add(extra).unwrap_or(-extra);
+                let tx = self.fresh_id();
+                Self::record(TransactionType::Withdrawal, client, tx, Some(amount))
+            }
+            // Id 0 is never handed out.
+            _ => Self::record(TransactionType::Dispute, client, TxId(0), None),
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = TransactionInput;
+
+    fn next(&mut self) -> Option<TransactionInput> {
+        if self.emitted >= self.config.transactions {
+            return None;
+        }
+        self.emitted += 1;
+
+        if self.rng.chance(self.config.invalid_rate) {
+            return Some(self.invalid());
+        }
+        if !self.follow_ups.is_empty()
+            && self.rng.chance(FOLLOW_UP_SHARE)
+            && let Some(record) = self.follow_up()
+        {
+            return Some(record);
+        }
+
+        let mix = self.config.mix;
+        let weights = [mix.deposit, mix.withdrawal, mix.authorize, mix.refund];
+        let total: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+        let mut roll = self.rng.below(total.max(1));
+        let mut choice = 0;
+        for (i, &weight) in weights.iter().enumerate() {
+            if roll < u64::from(weight) {
+                choice = i;
+                break;
+            }
+            roll -= u64::from(weight);
+        }
+        let record = match choice {
+            1 => self.withdrawal(),
+            2 => self.authorize(),
+            3 => self.refund(),
+            _ => None,
+        };
+        // Anything that cannot apply right now (an unknown client, no funds)
+        // becomes a deposit instead.
+        Some(record.unwrap_or_else(|| self.deposit()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{AMOUNT_SCALE, PaymentEngine};
+
+    fn config() -> GeneratorConfig {
+        GeneratorConfig {
+            seed: 7,
+            clients: 500,
+            transactions: 5000,
+            mix: "deposit=50,withdrawal=25,authorize=15,refund=10"
+                .parse()
+                .unwrap(),
+            dispute_rate: 0.2,
+            chargeback_rate: 0.1,
+            invalid_rate: 0.05,
+            amounts: "lognormal:50:1.5".parse().unwrap(),
+        }
+    }
+
+    fn rows(config: GeneratorConfig) -> Vec<String> {
+        Generator::new(config)
+            .map(|r| format!("{:?},{},{},{:?}", r.tx_type, r.client, r.tx, r.amount))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_reproduces_the_stream() {
+        assert_eq!(rows(config()), rows(config()));
+        let other = GeneratorConfig {
+            seed: 8,
+            ..config()
+        };
+        assert_ne!(rows(config()), rows(other));
+    }
+
+    #[test]
+    fn engine_ends_in_expected_states() {
+        let mut generator = Generator::new(config());
+        let mut engine = PaymentEngine::new();
+        let mut counts: HashMap<TransactionType, u64> = HashMap::new();
+        let mut rejected = 0;
+        for record in generator.by_ref() {
+            *counts.entry(record.tx_type).or_default() += 1;
+            if engine.process(&record).is_err() {
+                rejected += 1;
+            }
+        }
+
+        let mut actual: Vec<Account> = engine.accounts().cloned().collect();
+        actual.sort_by_key(|account| account.client);
+        let expected: Vec<Account> = generator.expected_accounts().cloned().collect();
+        assert_eq!(actual, expected);
+
+        // Invalid rows plus deposits to accounts locked by a chargeback.
+        assert!((200..500).contains(&rejected), "rejected {rejected}");
+        for tx_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Capture,
+            TransactionType::Void,
+            TransactionType::Refund,
+        ] {
+            assert!(
+                counts.get(&tx_type).is_some_and(|&n| n > 0),
+                "no {tx_type:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_amounts_stay_in_range() {
+        let config = GeneratorConfig {
+            amounts: "uniform:0.5:2".parse().unwrap(),
+            mix: "deposit=1".parse().unwrap(),
+            dispute_rate: 0.0,
+            transactions: 1000,
+            ..GeneratorConfig::default()
+        };
+        for record in Generator::new(config) {
+            let amount = record.amount.unwrap();
+            assert!(amount >= dec!(0.5) && amount <= dec!(2), "{amount}");
+            assert!(amount.scale() <= AMOUNT_SCALE);
+        }
+    }
+
+    #[test]
+    fn parses_mix_and_distributions() {
+        assert_eq!(
+            "withdrawal=1, deposit=3".parse::<TypeMix>(),
+            Ok(TypeMix {
+                deposit: 3,
+                withdrawal: 1,
+                authorize: 0,
+                refund: 0,
+            })
+        );
+        assert!("withdrawal=1".parse::<TypeMix>().is_err());
+        assert!("dispute=1,deposit=1".parse::<TypeMix>().is_err());
+        assert_eq!(
+            "lognormal:50:1.2".parse::<AmountDistribution>(),
+            Ok(AmountDistribution::LogNormal {
+                median: dec!(50),
+                sigma: 1.2
+            })
+        );
+        assert!("uniform:5:1".parse::<AmountDistribution>().is_err());
+        assert!("normal:5:1".parse::<AmountDistribution>().is_err());
+    }
+}
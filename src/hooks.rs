@@ -0,0 +1,269 @@
+use std::error::Error;
+
+use crate::account::Account;
+use crate::deposits::StoredDeposit;
+use crate::transaction::TransactionRecord;
+
+pub type HookError = Box<dyn Error + Send + Sync>;
+
+/// Read-only view handed to hooks.
+///
+/// `tx` carries its amount after the engine's precision policy. `deposit` is
+/// the stored deposit whose id matches `tx.tx`, if there is one, so
+/// dispute-style transactions can inspect what they refer to.
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    pub tx: &'a TransactionRecord,
+    pub account: Option<&'a Account>,
+    pub deposit: Option<StoredDeposit>,
+    pub seq: u64,
+    pub clock: Option<u64>,
+}
+
+/// Custom business rules run by the engine around every transaction.
+///
+/// Hooks run in registration order. `before_apply` sees the account as it is
+/// before the transaction and can veto it; the first veto wins and later hooks
+/// are skipped. `after_apply` only runs for transactions that were applied and
+/// sees the updated account.
+pub trait TransactionHook: Send {
+    fn before_apply(&mut self, _ctx: &HookContext<'_>) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    fn after_apply(&mut self, _ctx: &HookContext<'_>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::error::PaymentError;
+    use crate::transaction::{ClientId, TransactionInput, TransactionType, TxId};
+    use rust_decimal::Decimal;
+    use rust_decimal::dec;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    fn timed(record: TransactionInput, timestamp: u64) -> TransactionInput {
+        TransactionInput {
+            timestamp: Some(timestamp),
+            ..record
+        }
+    }
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[derive(Default)]
+    struct NewAccountWithdrawalLimit {
+        limit: Decimal,
+        opened_at: HashMap<ClientId, u64>,
+    }
+
+    impl TransactionHook for NewAccountWithdrawalLimit {
+        fn before_apply(&mut self, ctx: &HookContext<'_>) -> Result<(), HookError> {
+            if ctx.tx.tx_type != TransactionType::Withdrawal {
+                return Ok(());
+            }
+            let (Some(now), Some(opened)) = (ctx.clock, self.opened_at.get(&ctx.tx.client)) else {
+                return Ok(());
+            };
+            let amount = ctx.tx.amount.unwrap_or_default().to_decimal();
+            if now - opened < DAY && amount > self.limit {
+                let limit = self.limit;
+                return Err(
+                    format!("withdrawal of {amount} above {limit} in first 24 hours").into(),
+                );
+            }
+            Ok(())
+        }
+
+        fn after_apply(&mut self, ctx: &HookContext<'_>) {
+            if let Some(now) = ctx.clock {
+                self.opened_at.entry(ctx.tx.client).or_insert(now);
+            }
+        }
+    }
+
+    struct Recorder {
+        name: &'static str,
+        veto: bool,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TransactionHook for Recorder {
+        fn before_apply(&mut self, _ctx: &HookContext<'_>) -> Result<(), HookError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{}:before", self.name));
+            if self.veto {
+                return Err(format!("{} says no", self.name).into());
+            }
+            Ok(())
+        }
+
+        fn after_apply(&mut self, _ctx: &HookContext<'_>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{}:after", self.name));
+        }
+    }
+
+    #[test]
+    fn hook_vetoes_large_withdrawal_on_new_account() {
+        let mut engine = PaymentEngine::builder()
+            .hook(NewAccountWithdrawalLimit {
+                limit: dec!(100),
+                ..Default::default()
+            })
+            .build();
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Deposit, 1, 1, Some(dec!(500))),
+                0,
+            ))
+            .unwrap();
+
+        let withdrawal = timed(
+            TransactionInput::new(TransactionType::Withdrawal, 1, 2, Some(dec!(200))),
+            3600,
+        );
+        let result = engine.process(&withdrawal);
+        let Err(PaymentError::Vetoed(id, reason)) = result else {
+            panic!("expected veto, got {result:?}");
+        };
+        assert_eq!(id, TxId(2));
+        assert_eq!(
+            reason.to_string(),
+            "withdrawal of 200 above 100 in first 24 hours"
+        );
+
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Withdrawal, 1, 3, Some(dec!(50))),
+                3600,
+            ))
+            .unwrap();
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Withdrawal, 1, 4, Some(dec!(200))),
+                DAY,
+            ))
+            .unwrap();
+        let account = engine.accounts().next().unwrap();
+        assert_eq!(account.available, dec!(250));
+    }
+
+    #[test]
+    fn hooks_run_in_registration_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentEngine::builder()
+            .hook(Recorder {
+                name: "first",
+                veto: false,
+                calls: calls.clone(),
+            })
+            .hook(Recorder {
+                name: "second",
+                veto: false,
+                calls: calls.clone(),
+            })
+            .build();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "first:before",
+                "second:before",
+                "first:after",
+                "second:after"
+            ]
+        );
+    }
+
+    #[test]
+    fn first_veto_stops_later_hooks_and_leaves_state_untouched() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = PaymentEngine::builder()
+            .hook(Recorder {
+                name: "first",
+                veto: true,
+                calls: calls.clone(),
+            })
+            .hook(Recorder {
+                name: "second",
+                veto: false,
+                calls: calls.clone(),
+            })
+            .build();
+        let result = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(1)),
+        ));
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "transaction 1 vetoed: first says no"
+        );
+        assert_eq!(*calls.lock().unwrap(), ["first:before"]);
+        assert_eq!(engine.accounts().count(), 0);
+    }
+
+    #[test]
+    fn hook_sees_referenced_deposit() {
+        struct NoDisputesOverTen;
+
+        impl TransactionHook for NoDisputesOverTen {
+            fn before_apply(&mut self, ctx: &HookContext<'_>) -> Result<(), HookError> {
+                let is_dispute = ctx.tx.tx_type == TransactionType::Dispute;
+                match ctx.deposit {
+                    Some(deposit) if is_dispute && deposit.amount() > dec!(10).into() => {
+                        Err("dispute too large".into())
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let mut engine = PaymentEngine::builder().hook(NoDisputesOverTen).build();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(20)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                2,
+                Some(dec!(5)),
+            ))
+            .unwrap();
+
+        assert!(
+            engine
+                .process(&TransactionInput::new(TransactionType::Dispute, 1, 1, None))
+                .is_err()
+        );
+        engine
+            .process(&TransactionInput::new(TransactionType::Dispute, 1, 2, None))
+            .unwrap();
+        let account = engine.accounts().next().unwrap();
+        assert_eq!(account.held, dec!(5));
+    }
+}
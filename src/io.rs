@@ -1,45 +1,406 @@
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
+use std::sync::Arc;
 
-use crate::account::AccountOutput;
-use crate::engine::PaymentEngine;
+use csv::Position;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tracing::{Span, info, info_span, warn};
+
+use crate::account::{Account, AccountOutput, OutputConfig, OutputFormat};
+use crate::engine::{AccountDelta, PaymentEngine};
 use crate::error::PaymentError;
-use crate::transaction::TransactionRecord;
+use crate::events::Event;
+use crate::metrics;
+use crate::parse;
+use crate::reconcile::Break;
+use crate::risk::RiskHit;
+use crate::statement::{
+    DisputeChange, Statement, StatementBalance, StatementFormat, StatementLine,
+};
+use crate::transaction::{Provenance, TransactionInput, TxId};
 
 pub fn process_csv<R: Read>(reader: R) -> Result<PaymentEngine, PaymentError> {
-    let mut engine = PaymentEngine::new();
+    process_csv_with(reader, PaymentEngine::new())
+}
+
+/// Rows of the standard `type,client,tx,amount` schema go through the
+/// allocation-free parser in `parse`; other headers, and rows it does not
+/// recognize, are deserialized with serde.
+pub fn process_csv_with<R: Read>(
+    reader: R,
+    engine: PaymentEngine,
+) -> Result<PaymentEngine, PaymentError> {
+    process_rows(reader, engine, None, true)
+}
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_reader(reader);
+/// Like `process_csv_with`, with every row going through serde. Only there
+/// to benchmark the fast path against, behind the `bench-serde` feature.
+#[cfg(feature = "bench-serde")]
+pub fn process_csv_serde<R: Read>(
+    reader: R,
+    engine: PaymentEngine,
+) -> Result<PaymentEngine, PaymentError> {
+    process_rows(reader, engine, None, false)
+}
+
+/// Like `process_csv_with`, with `file` recorded in each record's provenance.
+pub fn process_csv_named<R: Read>(
+    reader: R,
+    file: &str,
+    engine: PaymentEngine,
+) -> Result<PaymentEngine, PaymentError> {
+    process_rows(reader, engine, Some(file.into()), true)
+}
 
-    for result in csv_reader.deserialize::<TransactionRecord>() {
-        let record = result?;
-        if let Err(e) = engine.process(&record) {
-            eprintln!("warning: skipping transaction: {e}");
+/// Runs the rows of `reader` through a fork of `engine` and returns how they
+/// would change each account. `engine` is left as it was.
+pub fn dry_run_csv<R: Read>(
+    reader: R,
+    file: &str,
+    engine: &mut PaymentEngine,
+) -> Result<Vec<AccountDelta>, PaymentError> {
+    let mut fork = engine.fork();
+    read_rows(reader, Some(file.into()), true, |record| {
+        let _entered = record_span(record).entered();
+        if let Err(e) = fork.process(record) {
+            warn!(reason = e.kind(), error = %e.inner(), "skipping transaction");
         }
-    }
+    })?;
+    fork.deltas()
+}
 
+fn process_rows<R: Read>(
+    reader: R,
+    mut engine: PaymentEngine,
+    file: Option<Arc<str>>,
+    fast_path: bool,
+) -> Result<PaymentEngine, PaymentError> {
+    read_rows(reader, file, fast_path, |record| apply(&mut engine, record))?;
     Ok(engine)
 }
 
+fn read_rows<R: Read>(
+    reader: R,
+    file: Option<Arc<str>>,
+    fast_path: bool,
+    mut f: impl FnMut(&TransactionInput),
+) -> Result<(), PaymentError> {
+    // Fields are trimmed per row below rather than with `csv::Trim::All`,
+    // which copies every record and would cost the fast path its lead.
+    let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let provenance = |position: Option<&Position>| {
+        position.map(|position| Provenance {
+            file: file.clone(),
+            line: position.line(),
+            byte: position.byte(),
+        })
+    };
+
+    let mut byte_headers = csv_reader.byte_headers()?.clone();
+    byte_headers.trim();
+    if fast_path && parse::is_fast_schema(&byte_headers) {
+        let mut row = csv::ByteRecord::new();
+        while csv_reader.read_byte_record(&mut row)? {
+            let mut record = match parse::parse_record(&row) {
+                Some(record) => record,
+                None => {
+                    row.trim();
+                    row.deserialize(Some(&byte_headers))?
+                }
+            };
+            record.provenance = provenance(row.position());
+            f(&record);
+        }
+    } else {
+        let mut headers = csv_reader.headers()?.clone();
+        headers.trim();
+        let mut row = csv::StringRecord::new();
+        while csv_reader.read_record(&mut row)? {
+            row.trim();
+            let mut record: TransactionInput = row.deserialize(Some(&headers))?;
+            record.provenance = provenance(row.position());
+            f(&record);
+        }
+    }
+
+    Ok(())
+}
+
+fn record_span(record: &TransactionInput) -> Span {
+    let provenance = record.provenance.as_ref();
+    info_span!(
+        "transaction",
+        tx = record.tx.0,
+        client = record.client.0,
+        r#type = record.tx_type.as_str(),
+        file = provenance.and_then(|p| p.file.as_deref()),
+        line = provenance.map_or(0, |p| p.line),
+        byte = provenance.map_or(0, |p| p.byte),
+    )
+}
+
+fn apply(engine: &mut PaymentEngine, record: &TransactionInput) {
+    let _entered = record_span(record).entered();
+
+    if let Err(e) = engine.process(record) {
+        warn!(reason = e.kind(), error = %e.inner(), "skipping transaction");
+    }
+    for hold in engine.take_expired_holds() {
+        info!(
+            authorization = hold.authorization.0,
+            client = hold.client.0,
+            amount = %hold.amount,
+            "authorization expired, hold released"
+        );
+    }
+}
+
 pub fn write_accounts<W: Write>(writer: W, engine: &PaymentEngine) -> Result<(), PaymentError> {
+    write_accounts_with(writer, engine, &OutputConfig::default())
+}
+
+pub fn write_accounts_with<W: Write>(
+    writer: W,
+    engine: &PaymentEngine,
+    config: &OutputConfig,
+) -> Result<(), PaymentError> {
+    write_account_list(writer, engine.accounts(), config)
+}
+
+/// Writes accounts that do not come from an engine, such as a generator's
+/// expected states, in the same format as `write_accounts_with`.
+pub fn write_account_list<'a, W: Write>(
+    mut writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+    config: &OutputConfig,
+) -> Result<(), PaymentError> {
+    let outputs = accounts
+        .into_iter()
+        .map(|account| AccountOutput::with_config(account, config));
+
+    match config.format {
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for output in outputs {
+                csv_writer.serialize(&output?)?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Json => {
+            let outputs = outputs.collect::<Result<Vec<_>, _>>()?;
+            serde_json::to_writer(&mut writer, &outputs)?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_transactions<W: Write>(
+    writer: W,
+    records: impl IntoIterator<Item = TransactionInput>,
+) -> Result<(), PaymentError> {
     let mut csv_writer = csv::Writer::from_writer(writer);
+    for record in records {
+        csv_writer.serialize(&record)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
 
-    for account in engine.accounts() {
-        let output = AccountOutput::from(account);
-        csv_writer.serialize(&output)?;
+pub fn write_risk_report<W: Write>(writer: W, hits: &[RiskHit]) -> Result<(), PaymentError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for hit in hits {
+        csv_writer.serialize(hit)?;
     }
+    csv_writer.flush()?;
+    Ok(())
+}
 
+pub fn write_breaks<W: Write>(writer: W, breaks: &[Break]) -> Result<(), PaymentError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for item in breaks {
+        csv_writer.serialize(item)?;
+    }
     csv_writer.flush()?;
     Ok(())
 }
 
+pub fn write_deltas<W: Write>(writer: W, deltas: &[AccountDelta]) -> Result<(), PaymentError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for delta in deltas {
+        csv_writer.serialize(delta)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+pub fn write_statement<W: Write>(
+    mut writer: W,
+    statement: &Statement,
+    format: StatementFormat,
+) -> Result<(), PaymentError> {
+    match format {
+        StatementFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(&mut writer);
+            csv_writer.serialize(StatementRow::balance("opening", &statement.opening))?;
+            for line in &statement.lines {
+                csv_writer.serialize(StatementRow::line(line))?;
+            }
+            csv_writer.serialize(StatementRow::balance("closing", &statement.closing))?;
+            csv_writer.flush()?;
+        }
+        StatementFormat::Json => {
+            serde_json::to_writer(&mut writer, statement)?;
+            writeln!(writer)?;
+        }
+        StatementFormat::Text => write_statement_text(&mut writer, statement)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A statement CSV row: one per line, framed by `opening` and `closing` rows
+/// that only carry balances. `locked` is only set on those two.
+#[derive(Serialize)]
+struct StatementRow<'a> {
+    seq: Option<u64>,
+    tx: Option<TxId>,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    amount: Option<Decimal>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    dispute: Option<DisputeChange>,
+    source: Option<&'a str>,
+    locked: Option<bool>,
+}
+
+impl<'a> StatementRow<'a> {
+    fn balance(kind: &'a str, balance: &StatementBalance) -> Self {
+        Self {
+            seq: None,
+            tx: None,
+            kind,
+            amount: None,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            dispute: None,
+            source: None,
+            locked: Some(balance.locked),
+        }
+    }
+
+    fn line(line: &'a StatementLine) -> Self {
+        Self {
+            seq: Some(line.seq),
+            tx: Some(line.tx),
+            kind: line.kind,
+            amount: line.amount,
+            available: line.available,
+            held: line.held,
+            total: line.total,
+            dispute: line.dispute,
+            source: line.source.as_deref(),
+            locked: None,
+        }
+    }
+}
+
+fn write_statement_text<W: Write>(writer: &mut W, statement: &Statement) -> std::io::Result<()> {
+    let config = OutputConfig::default();
+    let amount = |value| config.format_amount(value);
+    let balance = |b: &StatementBalance| {
+        format!(
+            "available {}, held {}, total {}{}",
+            amount(b.available),
+            amount(b.held),
+            amount(b.total),
+            if b.locked { " (locked)" } else { "" }
+        )
+    };
+    let range = match (statement.from, statement.to) {
+        (None, None) => String::new(),
+        (Some(from), None) => format!(", from seq {from}"),
+        (None, Some(to)) => format!(", up to seq {to}"),
+        (Some(from), Some(to)) => format!(", seq {from} to {to}"),
+    };
+
+    writeln!(writer, "Statement for client {}{range}", statement.client)?;
+    writeln!(writer, "Opening balance: {}", balance(&statement.opening))?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:>8} {:>10}  {:<16} {:>14} {:>14} {:>14} {:>14}  {:<12}  source",
+        "seq", "tx", "type", "amount", "available", "held", "total", "dispute"
+    )?;
+    for line in &statement.lines {
+        let dispute = line.dispute.map(DisputeChange::as_str).unwrap_or_default();
+        let source = line.source.as_deref().unwrap_or_default();
+        let row = format!(
+            "{:>8} {:>10}  {:<16} {:>14} {:>14} {:>14} {:>14}  {dispute:<12}  {source}",
+            line.seq,
+            line.tx,
+            line.kind,
+            line.amount.map(amount).unwrap_or_default(),
+            amount(line.available),
+            amount(line.held),
+            amount(line.total),
+        );
+        writeln!(writer, "{}", row.trim_end())?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Closing balance: {}", balance(&statement.closing))
+}
+
+pub fn write_metrics<W: Write>(mut writer: W, engine: &PaymentEngine) -> Result<(), PaymentError> {
+    writer.write_all(metrics::render(engine).as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_event<W: Write>(mut writer: W, event: &Event) -> Result<(), PaymentError> {
+    serde_json::to_writer(&mut writer, event)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+pub fn read_events<R: BufRead>(reader: R) -> Result<Vec<Event>, PaymentError> {
+    let mut events = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line)?);
+    }
+    Ok(events)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::{EngineConfig, HoldExpiry};
+    use crate::events::{Balances, EventKind};
+    use crate::transaction::{ClientId, TxId};
+    use proptest::prelude::*;
     use rust_decimal::dec;
 
+    fn sorted_output(engine: &PaymentEngine) -> Vec<String> {
+        let mut output = Vec::new();
+        write_accounts(&mut output, engine).unwrap();
+        let mut lines: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        lines
+    }
+
     #[test]
     fn process_csv_basic() {
         let csv_data = "\
@@ -49,8 +410,8 @@ deposit,2,2,20.0
 withdrawal,1,3,5.0
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let a1 = engine.accounts().find(|a| a.client == 1).unwrap();
-        let a2 = engine.accounts().find(|a| a.client == 2).unwrap();
+        let a1 = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
+        let a2 = engine.accounts().find(|a| a.client == ClientId(2)).unwrap();
         assert_eq!(a1.available, dec!(5));
         assert_eq!(a2.available, dec!(20));
     }
@@ -63,7 +424,7 @@ deposit , 1 , 1 , 10.0
 withdrawal , 1 , 2 , 5.0
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let account = engine.accounts().find(|a| a.client == 1).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(account.available, dec!(5));
     }
 
@@ -76,7 +437,7 @@ dispute,1,1,
 resolve,1,1,
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let account = engine.accounts().find(|a| a.client == 1).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(account.available, dec!(50));
         assert_eq!(account.held, dec!(0));
     }
@@ -90,7 +451,7 @@ dispute,1,1,
 chargeback,1,1,
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let account = engine.accounts().find(|a| a.client == 1).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(account.available, dec!(0));
         assert_eq!(account.held, dec!(0));
         assert!(account.locked);
@@ -128,7 +489,7 @@ dispute,1,1
 resolve,1,1
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let account = engine.accounts().find(|a| a.client == 1).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(account.available, dec!(50));
         assert_eq!(account.held, dec!(0));
     }
@@ -142,7 +503,7 @@ deposit,1,2,0.0001
 withdrawal,1,3,0.2346
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
-        let account = engine.accounts().find(|a| a.client == 1).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(account.available, dec!(1.0000));
 
         let mut output = Vec::new();
@@ -172,16 +533,516 @@ withdrawal,2,5,3.0
 ";
         let engine = process_csv(csv_data.as_bytes()).unwrap();
 
-        let a1 = engine.accounts().find(|a| a.client == 1).unwrap();
+        let a1 = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
         assert_eq!(a1.available, dec!(1.5));
         assert_eq!(a1.held, dec!(0));
-        assert_eq!(a1.total(), dec!(1.5));
+        assert_eq!(a1.total().unwrap(), dec!(1.5));
         assert!(!a1.locked);
 
-        let a2 = engine.accounts().find(|a| a.client == 2).unwrap();
+        let a2 = engine.accounts().find(|a| a.client == ClientId(2)).unwrap();
         assert_eq!(a2.available, dec!(2));
         assert_eq!(a2.held, dec!(0));
-        assert_eq!(a2.total(), dec!(2));
+        assert_eq!(a2.total().unwrap(), dec!(2));
         assert!(!a2.locked);
     }
+
+    #[test]
+    fn process_csv_authorize_capture_void() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,100.0
+authorize,1,2,30.0
+capture,1,2,10.0
+authorize,1,3,20.0
+void,1,3,
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
+        assert_eq!(account.available, dec!(70));
+        assert_eq!(account.held, dec!(20));
+        assert_eq!(account.total().unwrap(), dec!(90));
+    }
+
+    #[test]
+    fn process_csv_with_timestamp_expiry() {
+        let csv_data = "\
+type,client,tx,amount,timestamp
+deposit,1,1,100.0,10
+authorize,1,2,30.0,10
+deposit,2,3,1.0,100
+";
+        let engine = PaymentEngine::with_config(EngineConfig {
+            hold_expiry: HoldExpiry::AfterSeconds(60),
+            ..EngineConfig::default()
+        });
+        let engine = process_csv_with(csv_data.as_bytes(), engine).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
+        assert_eq!(account.available, dec!(100));
+        assert_eq!(account.held, dec!(0));
+    }
+
+    #[test]
+    fn process_csv_refund() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,100.0
+refund,1,1,40.0
+dispute,1,1,
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(1)).unwrap();
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(60));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn process_csv_account_lifecycle() {
+        let csv_data = "\
+type,client,tx,amount,metadata
+open,1,1,,segment=retail
+withdrawal,2,2,5.0,
+open,3,3,,
+deposit,3,4,1.0,
+withdrawal,3,5,1.0,
+close,3,6,,customer request
+deposit,3,7,1.0,
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+
+        let mut output = Vec::new();
+        write_accounts(&mut output, &engine).unwrap();
+        let mut lines: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "1,0.0000,0.0000,0.0000,false",
+                "3,0.0000,0.0000,0.0000,false"
+            ]
+        );
+        let closed = engine.accounts().find(|a| a.client == ClientId(3)).unwrap();
+        assert!(closed.closed);
+    }
+
+    #[test]
+    fn write_accounts_json_strings() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,1.5
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let config = OutputConfig {
+            format: OutputFormat::Json,
+            ..OutputConfig::default()
+        };
+
+        let mut output = Vec::new();
+        write_accounts_with(&mut output, &engine, &config).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}]\n"
+        );
+    }
+
+    #[test]
+    fn write_accounts_csv_ignores_numeric() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,1.5
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let config = OutputConfig {
+            scale: Some(2),
+            numeric: true,
+            ..OutputConfig::default()
+        };
+
+        let mut output = Vec::new();
+        write_accounts_with(&mut output, &engine, &config).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.50,0.00,1.50,false\n"
+        );
+    }
+
+    #[test]
+    fn process_csv_wide_ids() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,70000,5000000000,1.0
+dispute,70000,5000000000,
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let account = engine.accounts().find(|a| a.client == ClientId(70000)).unwrap();
+        assert_eq!(account.held, dec!(1));
+    }
+
+    #[test]
+    fn process_csv_rejects_non_numeric_client() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,abc,1,1.0
+";
+        let err = process_csv(csv_data.as_bytes()).err().unwrap().to_string();
+        assert!(err.contains("invalid client id `abc`"), "{err}");
+    }
+
+    #[test]
+    fn process_csv_rejects_negative_tx_id() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,-4,1.0
+";
+        let err = process_csv(csv_data.as_bytes()).err().unwrap().to_string();
+        assert!(err.contains("invalid transaction id `-4`"), "{err}");
+    }
+
+    #[test]
+    fn event_log_round_trip_rebuilds_accounts() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,4.0
+deposit,2,3,5.0
+dispute,2,3,
+chargeback,2,3,
+";
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut engine = PaymentEngine::new();
+        engine.subscribe(sender);
+        let engine = process_csv_with(csv_data.as_bytes(), engine).unwrap();
+
+        let mut log = Vec::new();
+        for event in receiver.try_iter() {
+            write_event(&mut log, &event).unwrap();
+        }
+        let events = read_events(log.as_slice()).unwrap();
+        let rebuilt = PaymentEngine::from_events(&events).unwrap();
+
+        assert_eq!(sorted_output(&engine), sorted_output(&rebuilt));
+    }
+
+    #[test]
+    fn overflowing_total_fails_output_instead_of_panicking() {
+        let event = Event {
+            seq: 1,
+            client: ClientId(1),
+            tx: TxId(1),
+            kind: EventKind::Withdrawn { amount: dec!(1) },
+            before: Balances::default(),
+            after: Balances {
+                available: dec!(70000000000000000000000000000),
+                held: dec!(70000000000000000000000000000),
+                locked: false,
+            },
+            provenance: None,
+        };
+        let engine = PaymentEngine::from_events([&event]).unwrap();
+
+        let err = write_accounts(Vec::new(), &engine).unwrap_err();
+        assert!(
+            matches!(err, PaymentError::BalanceOverflow(ClientId(1))),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn dry_run_csv_reports_deltas_only() {
+        let state = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+dispute,1,1,
+dispute,2,2,
+";
+        let proposed = "\
+type,client,tx,amount
+chargeback,1,1,
+chargeback,2,2,
+deposit,3,3,1.5
+withdrawal,4,4,1.0
+";
+        let mut engine = process_csv(state.as_bytes()).unwrap();
+        let before = sorted_output(&engine);
+
+        let deltas = dry_run_csv(proposed.as_bytes(), "proposed.csv", &mut engine).unwrap();
+        let mut output = Vec::new();
+        write_deltas(&mut output, &deltas).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+client,available,held,total,locked
+1,0,-10,-10,true
+2,0,-5,-5,true
+3,1.5,0,1.5,false
+"
+        );
+        assert_eq!(sorted_output(&engine), before);
+    }
+
+    #[test]
+    fn write_risk_report_format() {
+        use crate::risk::{RiskAction, RiskCheck, RiskConfig, RiskRule, Window};
+
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,10.0
+";
+        let engine = PaymentEngine::builder()
+            .risk(RiskConfig {
+                checks: vec![RiskCheck {
+                    rule: RiskRule::DepositThenFullWithdrawal {
+                        window: Window::Transactions(5),
+                        tolerance: dec!(0),
+                    },
+                    action: RiskAction::Flag,
+                }],
+            })
+            .build();
+        let engine = process_csv_with(csv_data.as_bytes(), engine).unwrap();
+
+        let mut output = Vec::new();
+        write_risk_report(&mut output, engine.risk_report()).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "seq,client,tx,rule,action,detail\n\
+             2,1,2,deposit_then_full_withdrawal,flag,withdrawal of 10 empties the account after depositing 10\n"
+        );
+    }
+
+    #[test]
+    fn skipped_rows_are_logged_with_their_source_line() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,50.0
+";
+        tracing::subscriber::with_default(subscriber, || {
+            process_csv(csv_data.as_bytes()).unwrap();
+        });
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let warning: serde_json::Value =
+            serde_json::from_str(logs.lines().next().unwrap()).unwrap();
+        assert_eq!(warning["level"], "WARN");
+        assert_eq!(warning["fields"]["reason"], "insufficient_funds");
+        assert_eq!(warning["span"]["tx"], 2);
+        assert_eq!(warning["span"]["client"], 1);
+        assert_eq!(warning["span"]["type"], "withdrawal");
+        assert_eq!(warning["span"]["line"], 3);
+        assert_eq!(warning["span"]["byte"], 39);
+        assert_eq!(
+            warning["fields"]["error"],
+            "insufficient funds for client 1: need 50, have 10"
+        );
+    }
+
+    #[test]
+    fn write_breaks_format() {
+        use crate::reconcile::{read_balances, reconcile};
+
+        let engine = process_csv("type,client,tx,amount\ndeposit,1,1,10.0\n".as_bytes()).unwrap();
+        let bank = "client,available,held,locked\n1,9.5,0,true\n2,1,0,false\n";
+        let breaks = reconcile(&engine, &read_balances(bank.as_bytes()).unwrap(), dec!(0));
+
+        let mut output = Vec::new();
+        write_breaks(&mut output, &breaks).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,break,engine,external,difference\n\
+             1,available,10,9.5,0.5\n\
+             1,total,10,9.5,0.5\n\
+             1,locked,false,true,\n\
+             2,missing_internal,,,\n"
+        );
+    }
+
+    #[test]
+    fn write_statement_text_report() {
+        use crate::statement::Statement;
+        use std::sync::mpsc;
+
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,2.5
+dispute,1,2,
+resolve,1,2,
+";
+        let (sender, receiver) = mpsc::channel();
+        let engine = PaymentEngine::builder().subscriber(sender).build();
+        drop(process_csv_named(csv_data.as_bytes(), "input.csv", engine).unwrap());
+        let events: Vec<Event> = receiver.try_iter().collect();
+        let statement = Statement::from_events(ClientId(1), &events, Some(2), None).unwrap();
+
+        let mut output = Vec::new();
+        write_statement(&mut output, &statement, StatementFormat::Text).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+Statement for client 1, from seq 2
+Opening balance: available 10.0000, held 0.0000, total 10.0000
+
+     seq         tx  type                     amount      available           held          total  dispute       source
+       2          2  deposited                2.5000        12.5000         0.0000        12.5000                input.csv:3 (byte 39)
+       3          2  dispute_opened           2.5000        10.0000         2.5000        12.5000  opened        input.csv:4 (byte 55)
+       4          2  dispute_resolved         2.5000        12.5000         0.0000        12.5000  resolved      input.csv:5 (byte 68)
+
+Closing balance: available 12.5000, held 0.0000, total 12.5000
+"
+        );
+    }
+
+    #[test]
+    fn write_statement_csv_frames_lines_with_balances() {
+        use crate::statement::Statement;
+        use std::sync::mpsc;
+
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,2.5
+withdrawal,1,3,1.5
+";
+        let (sender, receiver) = mpsc::channel();
+        let engine = PaymentEngine::builder().subscriber(sender).build();
+        drop(process_csv_named(csv_data.as_bytes(), "input.csv", engine).unwrap());
+        let events: Vec<Event> = receiver.try_iter().collect();
+        let statement = Statement::from_events(ClientId(1), &events, Some(2), None).unwrap();
+
+        let mut output = Vec::new();
+        write_statement(&mut output, &statement, StatementFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+seq,tx,type,amount,available,held,total,dispute,source,locked
+,,opening,,10,0,10,,,false
+2,2,deposited,2.5,12.5,0,12.5,,input.csv:3 (byte 39),
+3,3,withdrawn,1.5,11,0,11,,input.csv:4 (byte 55),
+,,closing,,11,0,11,,,false
+"
+        );
+    }
+
+    /// Events and the final result (or error message) of a run through the
+    /// fast or the serde path.
+    fn run_path(csv_data: &[u8], fast_path: bool) -> (Vec<Event>, Result<Vec<String>, String>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let engine = PaymentEngine::builder().subscriber(sender).build();
+        let result = process_rows(csv_data, engine, None, fast_path)
+            .map(|engine| {
+                let mut accounts: Vec<String> =
+                    engine.accounts().map(|a| format!("{a:?}")).collect();
+                accounts.sort();
+                accounts
+            })
+            .map_err(|e| e.to_string());
+        (receiver.try_iter().collect(), result)
+    }
+
+    #[test]
+    fn fast_path_matches_serde_on_example_files() {
+        for name in ["sample.csv", "missing_columns.csv", "large.csv"] {
+            let path = format!("{}/example_csvs/{name}", env!("CARGO_MANIFEST_DIR"));
+            let csv_data = std::fs::read(path).unwrap();
+            assert!(
+                run_path(&csv_data, true) == run_path(&csv_data, false),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn fast_path_falls_back_for_other_headers() {
+        let csv_data = "\
+client,type,tx,amount
+1,deposit,1,10.0
+";
+        let (_, result) = run_path(csv_data.as_bytes(), true);
+        assert_eq!(result.unwrap().len(), 1);
+    }
+
+    fn arb_field(common: &'static str, odd: &'static [&'static str]) -> BoxedStrategy<String> {
+        prop_oneof![
+            8 => proptest::string::string_regex(common).unwrap(),
+            1 => proptest::sample::select(odd).prop_map(String::from),
+        ]
+        .boxed()
+    }
+
+    fn arb_row() -> impl Strategy<Value = String> {
+        let tx_type = arb_field(
+            "(deposit|withdrawal|dispute|resolve|chargeback|authorize|capture|void|refund)",
+            &["Deposit", "transfer", ""],
+        );
+        let id = arb_field(
+            "[0-9]{1,2}",
+            &["007", "+3", "1.0", "99999999999999999999", ""],
+        );
+        let amount = arb_field(
+            "([0-9]{1,4}(\\.[0-9]{1,5})?)?",
+            &[
+                "-1.5",
+                "+2",
+                "1e2",
+                ".5",
+                "5.",
+                "0.00",
+                "12345.678901234567",
+                "1_000",
+                "x",
+            ],
+        );
+        let extra = proptest::option::weighted(0.05, Just("9".to_string()));
+        (tx_type, id.clone(), id, amount, extra, 0..20u8).prop_map(
+            |(tx_type, client, tx, amount, extra, shape)| match (shape, extra) {
+                (0, _) => format!("{tx_type},{client},{tx}"),
+                (1, _) => format!(" {tx_type} , {client} ,{tx}, {amount} "),
+                (_, Some(extra)) => format!("{tx_type},{client},{tx},{amount},{extra}"),
+                (_, None) => format!("{tx_type},{client},{tx},{amount}"),
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn fast_path_matches_serde(rows in proptest::collection::vec(arb_row(), 0..40)) {
+            let csv_data = format!("type,client,tx,amount\n{}\n", rows.join("\n"));
+            prop_assert_eq!(
+                run_path(csv_data.as_bytes(), true),
+                run_path(csv_data.as_bytes(), false)
+            );
+        }
+    }
 }
@@ -1,5 +1,16 @@
 pub mod account;
+pub mod amount;
+pub mod audit;
+pub mod deposits;
 pub mod engine;
 pub mod error;
+pub mod events;
+pub mod generate;
+pub mod hooks;
 pub mod io;
+pub mod metrics;
+pub mod parse;
+pub mod reconcile;
+pub mod risk;
+pub mod statement;
 pub mod transaction;
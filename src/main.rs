@@ -1,35 +1,591 @@
-use std::fs::File;
-use std::io::{self, BufReader};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, Mutex, mpsc};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_decimal::{Decimal, RoundingStrategy};
+use tracing::error;
+use tracing_subscriber::filter::LevelFilter;
 
-use payments_engine::io::{process_csv, write_accounts};
+use payments_engine::account::{OutputConfig, OutputFormat, parse_rounding};
+use payments_engine::audit::{self, AuditLog};
+use payments_engine::engine::{EngineConfig, HoldExpiry, PaymentEngine, PrecisionPolicy};
+use payments_engine::events::{Balances, Event, EventSubscriber};
+use payments_engine::generate::{AmountDistribution, Generator, GeneratorConfig, TypeMix};
+use payments_engine::io::{
+    dry_run_csv, process_csv_named, read_events, write_account_list, write_accounts_with,
+    write_breaks, write_deltas, write_event, write_metrics, write_risk_report, write_statement,
+    write_transactions,
+};
+use payments_engine::reconcile::read_balances;
+use payments_engine::risk::RiskConfig;
+use payments_engine::statement::{Statement, StatementFormat};
+use payments_engine::transaction::ClientId;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true)]
+    file: Option<PathBuf>,
+
+    /// TOML file with engine settings; the flags below override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Start from the state rebuilt from this event log, as written by --events-out
+    #[arg(long)]
+    state: Option<PathBuf>,
+
+    /// Print the per-client balance changes the input would cause instead of applying it
+    #[arg(
+        long,
+        requires = "state",
+        conflicts_with_all = ["events_out", "audit_log", "metrics_out", "risk_report"]
+    )]
+    dry_run: bool,
+
+    /// Release uncaptured authorization holds after this many transactions
+    #[arg(long, conflicts_with = "hold_expiry_secs")]
+    hold_expiry_txs: Option<u64>,
+
+    /// Release uncaptured authorization holds after this many seconds of input time
+    #[arg(long)]
+    hold_expiry_secs: Option<u64>,
+
+    /// Reject transactions whose amount exceeds this value
+    #[arg(long)]
+    max_amount: Option<Decimal>,
+
+    /// Forget undisputed deposits after this many transactions; later disputes of them are rejected
+    #[arg(long)]
+    dispute_horizon: Option<u64>,
+
+    /// How to handle amounts with more than 4 decimal places: reject (default, these rows used to
+    /// be accepted), round or truncate
+    #[arg(long)]
+    precision: Option<PrecisionPolicy>,
+
+    /// Output format: csv or json
+    #[arg(long, default_value = "csv")]
+    format: OutputFormat,
+
+    /// Decimal places in the output, or `full` for the internal precision
+    #[arg(long, default_value = "4", value_parser = parse_scale)]
+    output_scale: Scale,
+
+    /// Rounding applied to output values: half-even, half-up, half-down, truncate, floor or ceil
+    #[arg(long, default_value = "truncate", value_parser = parse_rounding)]
+    rounding: RoundingStrategy,
+
+    /// Emit JSON numbers instead of strings (JSON output only)
+    #[arg(long)]
+    numeric: bool,
+
+    /// Append every domain event as a JSON line to this file
+    #[arg(long)]
+    events_out: Option<PathBuf>,
+
+    /// TOML file with velocity and fraud checks
+    #[arg(long)]
+    risk_rules: Option<PathBuf>,
+
+    /// Write every risk rule hit as CSV to this file
+    #[arg(long)]
+    risk_report: Option<PathBuf>,
+
+    /// Write Prometheus-style metrics for the run to this file
+    #[arg(long)]
+    metrics_out: Option<PathBuf>,
+
+    /// Append a hash-chained audit entry for every transaction to this file
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Checkpoint account state in the audit log every this many transactions
+    #[arg(long, default_value = "1000")]
+    audit_checkpoint_every: u64,
+
+    /// Diagnostic log format on stderr
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    /// Minimum log level: off, error, warn, info, debug or trace
+    #[arg(long, default_value = "info", global = true)]
+    log_level: LevelFilter,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Recompute an audit log's hash chain and report the first broken entry
+    VerifyAudit { file: PathBuf },
+
+    /// Process a transaction file and compare the result with external balances
+    Reconcile {
+        transactions: PathBuf,
+
+        /// Bank balances as CSV: client,available,held[,total],locked
+        balances: PathBuf,
+
+        /// TOML file with engine settings, as for a normal run
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Largest absolute difference that is not reported as a break
+        #[arg(long, default_value = "0")]
+        tolerance: Decimal,
+
+        /// Write the break report to this file instead of stdout
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+
+    /// Process a transaction file and print one client's statement
+    Statement {
+        file: PathBuf,
+
+        #[arg(long)]
+        client: u64,
+
+        /// TOML file with engine settings, as for a normal run
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// First sequence number (1-based input row) to include
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Last sequence number to include
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Statement format: csv, json or text
+        #[arg(long, default_value = "text")]
+        format: StatementFormat,
+    },
+
+    /// Write a reproducible synthetic transaction file to stdout
+    Generate {
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        #[arg(long, default_value_t = 1000)]
+        clients: u64,
+
+        #[arg(long, default_value_t = 10_000)]
+        transactions: u64,
+
+        /// Relative weights, e.g. deposit=60,withdrawal=25,authorize=10,refund=5
+        #[arg(long, default_value = "deposit=75,withdrawal=25")]
+        mix: TypeMix,
+
+        /// Share of deposits that are disputed later on
+        #[arg(long, default_value_t = 0.05, value_parser = parse_rate)]
+        dispute_rate: f64,
+
+        /// Share of disputes that end in a chargeback
+        #[arg(long, default_value_t = 0.3, value_parser = parse_rate)]
+        chargeback_rate: f64,
+
+        /// Share of rows the engine should reject
+        #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+        invalid_rate: f64,
+
+        /// uniform:MIN:MAX or lognormal:MEDIAN:SIGMA
+        #[arg(long, default_value = "uniform:1:10000")]
+        amounts: AmountDistribution,
+
+        /// Also write the account states the engine should end up with
+        #[arg(long)]
+        expected: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+fn init_logging(format: LogFormat, level: LevelFilter) {
+    let builder = tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(level)
+        .with_target(false)
+        .with_ansi(io::stderr().is_terminal());
+    match format {
+        LogFormat::Text => builder.without_time().init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Scale(Option<u32>);
+
+fn parse_scale(s: &str) -> Result<Scale, String> {
+    if s == "full" {
+        return Ok(Scale(None));
+    }
+    s.parse()
+        .map(|scale| Scale(Some(scale)))
+        .map_err(|_| format!("invalid scale `{s}` (expected a number or `full`)"))
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "invalid rate `{s}` (expected a number from 0 to 1)"
+        )),
+    }
 }
 
 fn main() {
     let args = Args::parse();
+    init_logging(args.log_format, args.log_level);
+
+    match &args.command {
+        Some(Command::VerifyAudit { file }) => verify_audit(file),
+        Some(Command::Reconcile {
+            transactions,
+            balances,
+            config,
+            tolerance,
+            report,
+        }) => reconcile(
+            transactions,
+            balances,
+            load_config(config.as_deref()),
+            *tolerance,
+            report.as_deref(),
+        ),
+        Some(Command::Statement {
+            file,
+            client,
+            config,
+            from,
+            to,
+            format,
+        }) => statement(
+            file,
+            ClientId(*client),
+            load_config(config.as_deref()),
+            *from,
+            *to,
+            *format,
+        ),
+        Some(Command::Generate {
+            seed,
+            clients,
+            transactions,
+            mix,
+            dispute_rate,
+            chargeback_rate,
+            invalid_rate,
+            amounts,
+            expected,
+        }) => generate(
+            GeneratorConfig {
+                seed: *seed,
+                clients: *clients,
+                transactions: *transactions,
+                mix: *mix,
+                dispute_rate: *dispute_rate,
+                chargeback_rate: *chargeback_rate,
+                invalid_rate: *invalid_rate,
+                amounts: *amounts,
+            },
+            expected.as_deref(),
+        ),
+        None => run(&args),
+    }
+}
+
+fn verify_audit(path: &Path) {
+    let file = File::open(path).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "cannot open audit log");
+        process::exit(1);
+    });
+    match audit::verify(BufReader::new(file)) {
+        Ok(summary) => println!(
+            "ok: {} entries, {} checkpoints, head {}",
+            summary.entries, summary.checkpoints, summary.head
+        ),
+        Err(e) => {
+            println!("FAILED: {e}");
+            process::exit(1);
+        }
+    }
+}
 
-    let file = File::open(&args.file).unwrap_or_else(|e| {
-        eprintln!("Error opening {}: {e}", args.file.display());
+/// Reads `--config`, or returns the defaults without one.
+fn load_config(path: Option<&Path>) -> EngineConfig {
+    let Some(path) = path else {
+        return EngineConfig::default();
+    };
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "cannot read config");
+        process::exit(1);
+    });
+    EngineConfig::from_toml(&text).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "invalid config");
+        process::exit(1);
+    })
+}
+
+/// Exits with status 2 when there are breaks, 1 on errors.
+fn reconcile(
+    transactions: &Path,
+    balances: &Path,
+    config: EngineConfig,
+    tolerance: Decimal,
+    report: Option<&Path>,
+) {
+    let open = |path: &Path| {
+        File::open(path).map(BufReader::new).unwrap_or_else(|e| {
+            error!(path = %path.display(), error = %e, "cannot open input");
+            process::exit(1);
+        })
+    };
+    let name = transactions.display().to_string();
+    let engine = PaymentEngine::builder().config(config).build();
+    let engine = process_csv_named(open(transactions), &name, engine).unwrap_or_else(|e| {
+        error!(error = %e, "cannot process input");
+        process::exit(1);
+    });
+    let external = read_balances(open(balances)).unwrap_or_else(|e| {
+        error!(path = %balances.display(), error = %e, "cannot read balances");
+        process::exit(1);
+    });
+
+    let breaks = payments_engine::reconcile::reconcile(&engine, &external, tolerance);
+    let result = match report {
+        Some(path) => File::create(path)
+            .map_err(Into::into)
+            .and_then(|file| write_breaks(file, &breaks)),
+        None => write_breaks(io::stdout().lock(), &breaks),
+    };
+    if let Err(e) = result {
+        error!(error = %e, "cannot write break report");
+        process::exit(1);
+    }
+    if !breaks.is_empty() {
+        process::exit(2);
+    }
+}
+
+fn statement(
+    path: &Path,
+    client: ClientId,
+    config: EngineConfig,
+    from: Option<u64>,
+    to: Option<u64>,
+    format: StatementFormat,
+) {
+    let file = File::open(path).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "cannot open input");
+        process::exit(1);
+    });
+    let (sender, receiver) = mpsc::channel();
+    let engine = PaymentEngine::builder()
+        .config(config)
+        .subscriber(move |event: &Event| {
+            if event.client == client {
+                let _ = sender.send(event.clone());
+            }
+        })
+        .build();
+    let name = path.display().to_string();
+    let engine = process_csv_named(BufReader::new(file), &name, engine).unwrap_or_else(|e| {
+        error!(error = %e, "cannot process input");
+        process::exit(1);
+    });
+    drop(engine);
+
+    let events: Vec<Event> = receiver.try_iter().collect();
+    let result = Statement::from_events(client, &events, from, to)
+        .and_then(|statement| write_statement(io::stdout().lock(), &statement, format));
+    if let Err(e) = result {
+        error!(error = %e, "cannot produce statement");
+        process::exit(1);
+    }
+}
+
+fn generate(config: GeneratorConfig, expected: Option<&Path>) {
+    let mut generator = Generator::new(config);
+    let stdout = BufWriter::new(io::stdout().lock());
+    if let Err(e) = write_transactions(stdout, &mut generator) {
+        error!(error = %e, "cannot write transactions");
+        process::exit(1);
+    }
+    if let Some(path) = expected {
+        let result = File::create(path).map_err(Into::into).and_then(|file| {
+            write_account_list(
+                BufWriter::new(file),
+                generator.expected_accounts(),
+                &OutputConfig::default(),
+            )
+        });
+        if let Err(e) = result {
+            error!(path = %path.display(), error = %e, "cannot write expected accounts");
+            process::exit(1);
+        }
+    }
+}
+
+fn run(args: &Args) {
+    let path = args.file.as_deref().expect("clap requires the input file");
+    let file = File::open(path).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "cannot open input");
         process::exit(1);
     });
 
     let reader = BufReader::new(file);
+    let name = path.display().to_string();
+
+    let mut config = load_config(args.config.as_deref());
+
+    match (args.hold_expiry_txs, args.hold_expiry_secs) {
+        (Some(n), _) => config.hold_expiry = HoldExpiry::AfterTransactions(n),
+        (None, Some(secs)) => config.hold_expiry = HoldExpiry::AfterSeconds(secs),
+        (None, None) => {}
+    }
+    if let Some(max_amount) = args.max_amount {
+        config.max_amount = Some(max_amount);
+    }
+    if let Some(precision) = args.precision {
+        config.precision = precision;
+    }
+    if let Some(horizon) = args.dispute_horizon {
+        config.dispute_horizon = Some(horizon);
+    }
+    if let Some(path) = &args.risk_rules {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| {
+            error!(path = %path.display(), error = %e, "cannot read risk rules");
+            process::exit(1);
+        });
+        config.risk = RiskConfig::from_toml(&text).unwrap_or_else(|e| {
+            error!(path = %path.display(), error = %e, "invalid risk rules");
+            process::exit(1);
+        });
+    }
+
+    let mut engine = PaymentEngine::builder()
+        .config(config)
+        .metrics(args.metrics_out.is_some())
+        .build();
 
-    let engine = process_csv(reader).unwrap_or_else(|e| {
-        eprintln!("Error processing CSV: {e}");
+    let state_seq = args.state.as_ref().map(|path| {
+        let events = File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| read_events(BufReader::new(file)))
+            .unwrap_or_else(|e| {
+                error!(path = %path.display(), error = %e, "cannot read state");
+                process::exit(1);
+            });
+        for event in &events {
+            if let Err(e) = engine.apply_event(event) {
+                error!(path = %path.display(), error = %e, "invalid state");
+                process::exit(1);
+            }
+        }
+        if let Err(violation) = engine.check_invariants() {
+            error!(path = %path.display(), error = %violation, "inconsistent state");
+            process::exit(1);
+        }
+        events.last().map_or(0, |event| event.seq)
+    });
+
+    if args.dry_run {
+        let deltas = dry_run_csv(reader, &name, &mut engine).unwrap_or_else(|e| {
+            error!(error = %e, "cannot process input");
+            process::exit(1);
+        });
+        if let Err(e) = write_deltas(io::stdout().lock(), &deltas) {
+            error!(error = %e, "cannot write output");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.events_out {
+        let file = File::create(path).unwrap_or_else(|e| {
+            error!(path = %path.display(), error = %e, "cannot create events file");
+            process::exit(1);
+        });
+        let mut writer = BufWriter::new(file);
+        engine.subscribe(move |event: &Event| {
+            if let Err(e) = write_event(&mut writer, event) {
+                error!(error = %e, "cannot write event");
+            }
+        });
+    }
+    let audit = args.audit_log.as_ref().map(|path| {
+        let file = File::create(path).unwrap_or_else(|e| {
+            error!(path = %path.display(), error = %e, "cannot create audit log");
+            process::exit(1);
+        });
+        let mut log = AuditLog::new(BufWriter::new(file), args.audit_checkpoint_every);
+        if let Some(seq) = state_seq {
+            let accounts = engine.accounts().map(|a| (a.client, Balances::of(a)));
+            if let Err(e) = log.opening(seq, accounts) {
+                error!(path = %path.display(), error = %e, "cannot write audit log");
+                process::exit(1);
+            }
+        }
+        Arc::new(Mutex::new(log))
+    });
+    if let Some(audit) = &audit {
+        let audit = audit.clone();
+        engine.subscribe(move |event: &Event| audit.lock().unwrap().on_event(event));
+    }
+
+    let engine = process_csv_named(reader, &name, engine).unwrap_or_else(|e| {
+        error!(error = %e, "cannot process input");
         process::exit(1);
     });
 
+    if let Some(audit) = &audit {
+        let mut audit = audit.lock().unwrap();
+        if let Err(e) = audit.checkpoint().and_then(|()| audit.flush()) {
+            error!(error = %e, "cannot finish audit log");
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.risk_report {
+        let result = File::create(path)
+            .map_err(Into::into)
+            .and_then(|file| write_risk_report(file, engine.risk_report()));
+        if let Err(e) = result {
+            error!(path = %path.display(), error = %e, "cannot write risk report");
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.metrics_out {
+        let result = File::create(path)
+            .map_err(Into::into)
+            .and_then(|file| write_metrics(file, &engine));
+        if let Err(e) = result {
+            error!(path = %path.display(), error = %e, "cannot write metrics");
+            process::exit(1);
+        }
+    }
+
     let stdout = io::stdout();
-    if let Err(e) = write_accounts(stdout.lock(), &engine) {
-        eprintln!("Error writing output: {e}");
+    let output = OutputConfig {
+        format: args.format,
+        scale: args.output_scale.0,
+        rounding: args.rounding,
+        numeric: args.numeric,
+    };
+    if let Err(e) = write_accounts_with(stdout.lock(), &engine, &output) {
+        error!(error = %e, "cannot write output");
         process::exit(1);
     }
 }
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::engine::PaymentEngine;
+use crate::error::PaymentError;
+use crate::transaction::TransactionType;
+
+/// Upper bounds, in seconds, of the processing latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [
+    1e-6, 2.5e-6, 5e-6, 1e-5, 2.5e-5, 5e-5, 1e-4, 2.5e-4, 1e-3, 1e-2,
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+}
+
+/// Counters collected by the engine while processing. Gauges are read from
+/// the engine itself when rendering.
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    transactions: BTreeMap<&'static str, u64>,
+    rejections: BTreeMap<&'static str, u64>,
+    latency: Histogram,
+}
+
+impl Metrics {
+    pub fn record(
+        &mut self,
+        tx_type: TransactionType,
+        result: Result<(), &PaymentError>,
+        elapsed: Duration,
+    ) {
+        *self.transactions.entry(tx_type.as_str()).or_default() += 1;
+        if let Err(e) = result {
+            *self.rejections.entry(e.kind()).or_default() += 1;
+        }
+        self.latency.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn transactions(&self, tx_type: TransactionType) -> u64 {
+        self.transactions
+            .get(tx_type.as_str())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn rejections(&self, kind: &str) -> u64 {
+        self.rejections.get(kind).copied().unwrap_or(0)
+    }
+
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+/// Renders the engine's metrics in the Prometheus text exposition format.
+///
+/// Counters are empty unless the engine was built with metrics enabled.
+pub fn render(engine: &PaymentEngine) -> String {
+    let default = Metrics::default();
+    let metrics = engine.metrics().unwrap_or(&default);
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "payments_transactions_total",
+        "counter",
+        "Transactions processed, by type.",
+    );
+    for (tx_type, count) in &metrics.transactions {
+        let _ = writeln!(
+            out,
+            "payments_transactions_total{{type=\"{tx_type}\"}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "payments_rejections_total",
+        "counter",
+        "Rejected transactions, by error.",
+    );
+    for (reason, count) in &metrics.rejections {
+        let _ = writeln!(
+            out,
+            "payments_rejections_total{{reason=\"{reason}\"}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "payments_process_duration_seconds",
+        "histogram",
+        "Time spent processing a single transaction.",
+    );
+    let mut cumulative = 0;
+    for (le, count) in LATENCY_BUCKETS.iter().zip(metrics.latency.buckets) {
+        cumulative += count;
+        let _ = writeln!(
+            out,
+            "payments_process_duration_seconds_bucket{{le=\"{le}\"}} {cumulative}"
+        );
+    }
+    let _ = writeln!(
+        out,
+        "payments_process_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+        metrics.latency.count
+    );
+    let _ = writeln!(
+        out,
+        "payments_process_duration_seconds_sum {}",
+        metrics.latency.sum
+    );
+    let _ = writeln!(
+        out,
+        "payments_process_duration_seconds_count {}",
+        metrics.latency.count
+    );
+
+    let held: Decimal = engine
+        .accounts()
+        .map(|account| account.held.to_decimal())
+        .sum();
+    gauge(
+        &mut out,
+        "payments_accounts",
+        "Client accounts.",
+        engine.accounts().count(),
+    );
+    gauge(
+        &mut out,
+        "payments_stored_deposits",
+        "Deposits kept for dispute lookups.",
+        engine.deposit_count(),
+    );
+    gauge(
+        &mut out,
+        "payments_evicted_deposits",
+        "Deposits dropped after the dispute horizon.",
+        engine.evicted_deposits(),
+    );
+
+    let memory = engine.memory_usage();
+    header(
+        &mut out,
+        "payments_memory_bytes",
+        "gauge",
+        "Approximate heap bytes of the engine's tables.",
+    );
+    for (table, bytes) in [
+        ("accounts", memory.accounts),
+        ("deposits", memory.deposits.bytes),
+        ("authorizations", memory.authorizations),
+    ] {
+        let _ = writeln!(out, "payments_memory_bytes{{table=\"{table}\"}} {bytes}");
+    }
+
+    gauge(
+        &mut out,
+        "payments_held_total",
+        "Sum of held funds across all accounts.",
+        held,
+    );
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionInput;
+    use rust_decimal::dec;
+
+    #[test]
+    fn counts_transactions_and_rejections() {
+        let mut engine = PaymentEngine::builder().metrics(true).build();
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(10)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(10)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(dec!(50)),
+        ));
+        let _ = engine.process(&TransactionInput::new(TransactionType::Dispute, 1, 1, None));
+
+        let metrics = engine.metrics().unwrap();
+        assert_eq!(metrics.transactions(TransactionType::Deposit), 2);
+        assert_eq!(metrics.transactions(TransactionType::Withdrawal), 1);
+        assert_eq!(metrics.transactions(TransactionType::Dispute), 1);
+        assert_eq!(metrics.rejections("duplicate_transaction"), 1);
+        assert_eq!(metrics.rejections("insufficient_funds"), 1);
+        assert_eq!(metrics.latency().count(), 4);
+    }
+
+    #[test]
+    fn renders_text_exposition() {
+        let mut engine = PaymentEngine::builder().metrics(true).build();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                2,
+                2,
+                Some(dec!(2.5)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(TransactionType::Dispute, 2, 2, None))
+            .unwrap();
+        let _ = engine.process(&TransactionInput::new(TransactionType::Resolve, 1, 9, None));
+
+        let text = render(&engine);
+        assert!(text.contains("# TYPE payments_transactions_total counter\n"));
+        assert!(text.contains("payments_transactions_total{type=\"deposit\"} 2\n"));
+        assert!(text.contains("payments_rejections_total{reason=\"transaction_not_found\"} 1\n"));
+        assert!(text.contains("payments_process_duration_seconds_bucket{le=\"+Inf\"} 4\n"));
+        assert!(text.contains("payments_process_duration_seconds_count 4\n"));
+        assert!(text.contains("payments_accounts 2\n"));
+        assert!(text.contains("payments_stored_deposits 2\n"));
+        assert!(text.contains("payments_held_total 2.5\n"));
+    }
+
+    #[test]
+    fn latency_lands_in_first_matching_bucket() {
+        let mut histogram = Histogram::default();
+        histogram.observe(2e-6);
+        histogram.observe(1e-4);
+        histogram.observe(1.0);
+
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[6], 1);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), 2);
+        assert_eq!(histogram.count(), 3);
+    }
+}
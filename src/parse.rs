@@ -0,0 +1,191 @@
+//! Allocation-free parsing of the standard `type,client,tx,amount` schema.
+//!
+//! Every function returns `None` for anything outside the common shape
+//! (signs, exponents, very long numbers, unknown types, extra columns). The
+//! caller then falls back to serde, which stays the reference for what the
+//! input means and for the errors unusual rows produce.
+
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+
+use crate::transaction::{ClientId, TransactionInput, TransactionType, TxId};
+
+/// Header of files the fast path handles, after trimming.
+pub const HEADERS: [&[u8]; 4] = [b"type", b"client", b"tx", b"amount"];
+
+/// Digits that always fit a `u64` mantissa.
+const MAX_AMOUNT_DIGITS: usize = 19;
+
+/// Significant digits that survive a round trip through `f64`.
+const MAX_FLOAT_DIGITS: u32 = 15;
+
+pub fn is_fast_schema(headers: &ByteRecord) -> bool {
+    headers.iter().eq(HEADERS)
+}
+
+pub fn parse_record(row: &ByteRecord) -> Option<TransactionInput> {
+    if row.len() != HEADERS.len() {
+        return None;
+    }
+    // Trimmed here rather than by the reader, which would copy every row.
+    let field = |i: usize| row[i].trim_ascii();
+    Some(TransactionInput {
+        tx_type: parse_type(field(0))?,
+        client: ClientId(parse_id(field(1))?),
+        tx: TxId(parse_id(field(2))?),
+        amount: match field(3) {
+            b"" => None,
+            field => Some(parse_amount(field)?),
+        },
+        timestamp: None,
+        metadata: None,
+        provenance: None,
+    })
+}
+
+pub fn parse_type(field: &[u8]) -> Option<TransactionType> {
+    Some(match field {
+        b"deposit" => TransactionType::Deposit,
+        b"withdrawal" => TransactionType::Withdrawal,
+        b"dispute" => TransactionType::Dispute,
+        b"resolve" => TransactionType::Resolve,
+        b"chargeback" => TransactionType::Chargeback,
+        b"authorize" => TransactionType::Authorize,
+        b"capture" => TransactionType::Capture,
+        b"void" => TransactionType::Void,
+        b"refund" => TransactionType::Refund,
+        b"open" => TransactionType::Open,
+        b"close" => TransactionType::Close,
+        _ => return None,
+    })
+}
+
+pub fn parse_id(field: &[u8]) -> Option<u64> {
+    if field.is_empty() {
+        return None;
+    }
+    field.iter().try_fold(0u64, |value, &byte| {
+        let digit = byte.wrapping_sub(b'0');
+        if digit > 9 {
+            return None;
+        }
+        value.checked_mul(10)?.checked_add(u64::from(digit))
+    })
+}
+
+/// Parses `digits[.digits]` into the same `Decimal` the serde path produces.
+///
+/// csv infers the type of each field, so serde sees whole numbers as `u64`
+/// and anything with a decimal point as `f64`, which `Decimal` then reads
+/// back from its shortest round-trip text. Up to 15 significant digits that
+/// is the input itself with trailing zeros dropped; longer fractions are left
+/// to serde and its float rounding.
+pub fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    let (whole, fraction) = match field.iter().position(|&byte| byte == b'.') {
+        Some(dot) => (&field[..dot], &field[dot + 1..]),
+        None => (field, &b""[..]),
+    };
+    let has_dot = whole.len() < field.len();
+    if whole.is_empty()
+        || (has_dot && fraction.is_empty())
+        || whole.len() + fraction.len() > MAX_AMOUNT_DIGITS
+    {
+        return None;
+    }
+
+    let mut mantissa = 0u64;
+    for &byte in whole.iter().chain(fraction) {
+        let digit = byte.wrapping_sub(b'0');
+        if digit > 9 {
+            return None;
+        }
+        mantissa = mantissa * 10 + u64::from(digit);
+    }
+    if !has_dot {
+        return Some(Decimal::from(mantissa));
+    }
+
+    let trailing_zeros = fraction.iter().rev().take_while(|&&b| b == b'0').count();
+    let significant = (mantissa / 10u64.pow(trailing_zeros as u32)).checked_ilog10();
+    if significant.is_some_and(|log| log + 1 > MAX_FLOAT_DIGITS) {
+        return None;
+    }
+    Some(Decimal::from_i128_with_scale(i128::from(mantissa), fraction.len() as u32).normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serde_amount(text: &str) -> Option<Decimal> {
+        let csv_data = format!("type,client,tx,amount\ndeposit,1,1,{text}\n");
+        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
+        let record: TransactionInput = reader.deserialize().next()?.ok()?;
+        record.amount
+    }
+
+    #[test]
+    fn amounts_match_serde() {
+        for text in [
+            "0",
+            "1",
+            "10.0",
+            "0.0",
+            "0.0001",
+            "1113.3196",
+            "007.50",
+            "1.00000000000000000",
+            "123456789012.345",
+            "9999999999999999999",
+            "0.000000000000000001",
+        ] {
+            let fast = parse_amount(text.as_bytes()).unwrap();
+            let reference = serde_amount(text).unwrap();
+            assert_eq!(fast, reference, "{text}");
+            assert_eq!(fast.to_string(), reference.to_string(), "{text}");
+        }
+    }
+
+    #[test]
+    fn unusual_amounts_are_left_to_serde() {
+        for text in [
+            "-1",
+            "+1",
+            ".5",
+            "5.",
+            "1e3",
+            "1_000",
+            "1.2.3",
+            "12345678901234567890",
+            "1234567890.1234567",
+            "1 0",
+        ] {
+            assert_eq!(parse_amount(text.as_bytes()), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn ids_reject_overflow_and_non_digits() {
+        assert_eq!(parse_id(b"18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_id(b"18446744073709551616"), None);
+        assert_eq!(parse_id(b""), None);
+        assert_eq!(parse_id(b"-1"), None);
+        assert_eq!(parse_id(b"1.0"), None);
+    }
+
+    #[test]
+    fn records_need_exactly_four_fields() {
+        let row = ByteRecord::from(vec!["dispute", "1", "2", ""]);
+        let record = parse_record(&row).unwrap();
+        assert_eq!(record.tx_type, TransactionType::Dispute);
+        assert_eq!((record.client, record.tx), (ClientId(1), TxId(2)));
+        assert_eq!(record.amount, None);
+
+        assert!(parse_record(&ByteRecord::from(vec!["dispute", "1", "2"])).is_none());
+        assert!(parse_record(&ByteRecord::from(vec!["Deposit", "1", "2", "1"])).is_none());
+
+        let padded =
+            parse_record(&ByteRecord::from(vec![" deposit", " 1 ", "2\t", " 1.5"])).unwrap();
+        assert_eq!(padded.amount, Some(Decimal::new(15, 1)));
+    }
+}
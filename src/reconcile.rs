@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::engine::PaymentEngine;
+use crate::error::PaymentError;
+use crate::transaction::ClientId;
+
+/// One row of the bank's balance file. `total` defaults to `available + held`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExternalBalance {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    #[serde(default)]
+    pub total: Option<Decimal>,
+    pub locked: bool,
+}
+
+impl ExternalBalance {
+    /// `None` if `total` is absent and `available + held` overflows.
+    pub fn total(&self) -> Option<Decimal> {
+        self.total.or_else(|| self.available.checked_add(self.held))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakKind {
+    /// The engine has the client, the bank file does not.
+    MissingExternal,
+    /// The bank file has the client, the engine does not.
+    MissingInternal,
+    DuplicateExternal,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+/// A single reconciliation break. `difference` is engine minus external and is
+/// only set for balance mismatches where it does not overflow.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Break {
+    pub client: ClientId,
+    #[serde(rename = "break")]
+    pub kind: BreakKind,
+    pub engine: Option<String>,
+    pub external: Option<String>,
+    pub difference: Option<Decimal>,
+}
+
+pub fn read_balances<R: Read>(reader: R) -> Result<Vec<ExternalBalance>, PaymentError> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut balances = Vec::new();
+    for result in csv_reader.deserialize() {
+        balances.push(result?);
+    }
+    Ok(balances)
+}
+
+/// Compares every engine account with the bank's balances. Balance differences
+/// within `tolerance` (inclusive) are not breaks.
+pub fn reconcile(
+    engine: &PaymentEngine,
+    external: &[ExternalBalance],
+    tolerance: Decimal,
+) -> Vec<Break> {
+    let mut breaks = Vec::new();
+    let mut theirs: BTreeMap<ClientId, &ExternalBalance> = BTreeMap::new();
+    for balance in external {
+        if theirs.insert(balance.client, balance).is_some() {
+            breaks.push(Break {
+                client: balance.client,
+                kind: BreakKind::DuplicateExternal,
+                engine: None,
+                external: None,
+                difference: None,
+            });
+        }
+    }
+    let ours: BTreeMap<ClientId, &Account> = engine
+        .accounts()
+        .map(|account| (account.client, account))
+        .collect();
+
+    for (&client, account) in &ours {
+        let Some(balance) = theirs.get(&client) else {
+            breaks.push(Break {
+                client,
+                kind: BreakKind::MissingExternal,
+                engine: None,
+                external: None,
+                difference: None,
+            });
+            continue;
+        };
+        let amounts: [(BreakKind, Option<Decimal>, Option<Decimal>); 3] = [
+            (
+                BreakKind::Available,
+                Some(account.available.into()),
+                Some(balance.available),
+            ),
+            (
+                BreakKind::Held,
+                Some(account.held.into()),
+                Some(balance.held),
+            ),
+            (
+                BreakKind::Total,
+                account.total().map(Decimal::from),
+                balance.total(),
+            ),
+        ];
+        for (kind, ours, theirs) in amounts {
+            let difference = ours
+                .zip(theirs)
+                .and_then(|(ours, theirs)| ours.checked_sub(theirs));
+            if difference.is_none_or(|difference| difference.abs() > tolerance) {
+                breaks.push(Break {
+                    client,
+                    kind,
+                    engine: ours.map(|ours| ours.normalize().to_string()),
+                    external: theirs.map(|theirs| theirs.normalize().to_string()),
+                    difference: difference.map(|difference| difference.normalize()),
+                });
+            }
+        }
+        if account.locked != balance.locked {
+            breaks.push(Break {
+                client,
+                kind: BreakKind::Locked,
+                engine: Some(account.locked.to_string()),
+                external: Some(balance.locked.to_string()),
+                difference: None,
+            });
+        }
+    }
+    for &client in theirs.keys() {
+        if !ours.contains_key(&client) {
+            breaks.push(Break {
+                client,
+                kind: BreakKind::MissingInternal,
+                engine: None,
+                external: None,
+                difference: None,
+            });
+        }
+    }
+
+    breaks.sort_by_key(|b| (b.client, b.kind));
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::process_csv;
+    use rust_decimal::dec;
+
+    fn engine() -> PaymentEngine {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+dispute,2,2,
+deposit,3,3,1.0
+deposit,4,4,2.0
+dispute,4,4,
+chargeback,4,4,
+";
+        process_csv(csv_data.as_bytes()).unwrap()
+    }
+
+    fn kinds(breaks: &[Break]) -> Vec<(u64, BreakKind)> {
+        breaks.iter().map(|b| (b.client.0, b.kind)).collect()
+    }
+
+    #[test]
+    fn matching_balances_have_no_breaks() {
+        let bank = "\
+client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false
+2,0.0000,5.0000,5.0000,false
+3,1,0,1,false
+4,0,0,0,true
+";
+        let external = read_balances(bank.as_bytes()).unwrap();
+        assert!(reconcile(&engine(), &external, dec!(0)).is_empty());
+    }
+
+    #[test]
+    fn reports_every_kind_of_break() {
+        let bank = "\
+client,available,held,locked
+2,0.0,4.5,false
+3,1.0,0.0,false
+4,0.0,0.0,false
+5,3.0,0.0,false
+3,1.0,0.0,false
+";
+        let external = read_balances(bank.as_bytes()).unwrap();
+        let breaks = reconcile(&engine(), &external, dec!(0));
+
+        assert_eq!(
+            kinds(&breaks),
+            [
+                (1, BreakKind::MissingExternal),
+                (2, BreakKind::Held),
+                (2, BreakKind::Total),
+                (3, BreakKind::DuplicateExternal),
+                (4, BreakKind::Locked),
+                (5, BreakKind::MissingInternal),
+            ]
+        );
+        let held = &breaks[1];
+        assert_eq!(held.engine.as_deref(), Some("5"));
+        assert_eq!(held.external.as_deref(), Some("4.5"));
+        assert_eq!(held.difference, Some(dec!(0.5)));
+    }
+
+    #[test]
+    fn differences_within_tolerance_are_ignored() {
+        let bank = "\
+client,available,held,locked
+1,10.01,0,false
+";
+        let external = read_balances(bank.as_bytes()).unwrap();
+        let engine = process_csv("type,client,tx,amount\ndeposit,1,1,10\n".as_bytes()).unwrap();
+
+        assert!(reconcile(&engine, &external, dec!(0.01)).is_empty());
+        assert_eq!(
+            kinds(&reconcile(&engine, &external, dec!(0.001))),
+            [(1, BreakKind::Available), (1, BreakKind::Total)]
+        );
+    }
+
+    #[test]
+    fn overflowing_totals_and_differences_are_breaks() {
+        let bank = "\
+client,available,held,locked
+1,70000000000000000000000000000.0,70000000000000000000000000000.0,false
+2,-70000000000000000000000000000.0,0,false
+";
+        let external = read_balances(bank.as_bytes()).unwrap();
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,1
+deposit,2,2,70000000000000000000000000000.0
+";
+        let engine = process_csv(csv_data.as_bytes()).unwrap();
+        let breaks = reconcile(&engine, &external, dec!(0));
+
+        assert_eq!(
+            kinds(&breaks),
+            [
+                (1, BreakKind::Available),
+                (1, BreakKind::Held),
+                (1, BreakKind::Total),
+                (2, BreakKind::Available),
+                (2, BreakKind::Total),
+            ]
+        );
+        assert_eq!(breaks[2].external, None);
+        assert_eq!(breaks[2].difference, None);
+        assert_eq!(breaks[3].difference, None);
+    }
+}
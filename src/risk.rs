@@ -0,0 +1,801 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use rust_decimal::Decimal;
+use rust_decimal::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::transaction::{ClientId, TransactionRecord, TransactionType, TxId};
+
+/// A look-back window over a client's own history.
+///
+/// `Transactions(n)` covers the client's last `n` applied transactions,
+/// including the one being checked. `Seconds(s)` is measured against the
+/// `timestamp` column, so it only sees timestamped records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+    Transactions(usize),
+    Seconds(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RiskRule {
+    MaxWithdrawals {
+        count: usize,
+        window: Window,
+    },
+    MaxWithdrawnAmount {
+        amount: Decimal,
+        window: Window,
+    },
+    /// A withdrawal that leaves at most `tolerance` available after a deposit
+    /// in the window.
+    DepositThenFullWithdrawal {
+        window: Window,
+        #[serde(default)]
+        tolerance: Decimal,
+    },
+    DisputeRate {
+        max_rate: Decimal,
+        min_disputes: usize,
+        window: Window,
+    },
+}
+
+impl RiskRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MaxWithdrawals { .. } => "max_withdrawals",
+            Self::MaxWithdrawnAmount { .. } => "max_withdrawn_amount",
+            Self::DepositThenFullWithdrawal { .. } => "deposit_then_full_withdrawal",
+            Self::DisputeRate { .. } => "dispute_rate",
+        }
+    }
+
+    fn window(&self) -> Window {
+        match self {
+            Self::MaxWithdrawals { window, .. }
+            | Self::MaxWithdrawnAmount { window, .. }
+            | Self::DepositThenFullWithdrawal { window, .. }
+            | Self::DisputeRate { window, .. } => *window,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskAction {
+    Flag,
+    Reject,
+    Freeze,
+}
+
+impl fmt::Display for RiskAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Flag => "flag",
+            Self::Reject => "reject",
+            Self::Freeze => "freeze",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskCheck {
+    #[serde(flatten)]
+    pub rule: RiskRule,
+    pub action: RiskAction,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RiskConfig {
+    #[serde(default)]
+    pub checks: Vec<RiskCheck>,
+}
+
+impl RiskConfig {
+    pub fn from_toml(s: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskHit {
+    pub seq: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub rule: &'static str,
+    pub action: RiskAction,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Activity {
+    Deposit(Decimal),
+    Withdrawal(Decimal),
+    Dispute,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    timestamp: Option<u64>,
+    activity: Activity,
+}
+
+/// A client's risk history and flag, saved so a rolled-back batch can
+/// restore them.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientRisk {
+    history: Option<VecDeque<Entry>>,
+    flagged: bool,
+}
+
+/// Per-client history and rule evaluation used by `PaymentEngine::process`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RiskEngine {
+    config: RiskConfig,
+    max_transactions: usize,
+    max_seconds: Option<u64>,
+    history: HashMap<ClientId, VecDeque<Entry>>,
+    hits: Vec<RiskHit>,
+    pending: Vec<RiskHit>,
+    flagged: HashSet<ClientId>,
+}
+
+impl RiskEngine {
+    pub(crate) fn new(config: RiskConfig) -> Self {
+        let mut max_transactions = 0;
+        let mut max_seconds = None;
+        for check in &config.checks {
+            match check.rule.window() {
+                Window::Transactions(n) => max_transactions = max_transactions.max(n),
+                Window::Seconds(s) => max_seconds = Some(max_seconds.unwrap_or(0).max(s)),
+            }
+        }
+
+        Self {
+            config,
+            max_transactions,
+            max_seconds,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn hits(&self) -> &[RiskHit] {
+        &self.hits
+    }
+
+    pub(crate) fn flagged(&self) -> &HashSet<ClientId> {
+        &self.flagged
+    }
+
+    pub(crate) fn client_state(&self, client: ClientId) -> ClientRisk {
+        ClientRisk {
+            history: self.history.get(&client).cloned(),
+            flagged: self.flagged.contains(&client),
+        }
+    }
+
+    pub(crate) fn restore(&mut self, client: ClientId, state: ClientRisk) {
+        match state.history {
+            Some(history) => self.history.insert(client, history),
+            None => self.history.remove(&client),
+        };
+        if state.flagged {
+            self.flagged.insert(client);
+        } else {
+            self.flagged.remove(&client);
+        }
+    }
+
+    pub(crate) fn truncate_hits(&mut self, len: usize) {
+        self.hits.truncate(len);
+    }
+
+    /// Checks `tx` against every rule and returns the strongest action among
+    /// the hits. The hits are only reported once `keep_hits` or `record` is
+    /// called, so a transaction the engine goes on to reject leaves none.
+    pub(crate) fn evaluate(
+        &mut self,
+        tx: &TransactionRecord,
+        account: Option<&Account>,
+        seq: u64,
+    ) -> Option<RiskAction> {
+        self.pending.clear();
+        if self.config.checks.is_empty() {
+            return None;
+        }
+
+        let history = self.history.get(&tx.client);
+        let candidate = Entry {
+            timestamp: tx.timestamp,
+            activity: activity(tx),
+        };
+
+        let mut verdict = None;
+        for check in &self.config.checks {
+            let entries = in_window(history, candidate, check.rule.window());
+            let Some(detail) = triggered(&check.rule, &entries, candidate, account) else {
+                continue;
+            };
+
+            self.pending.push(RiskHit {
+                seq,
+                client: tx.client,
+                tx: tx.tx,
+                rule: check.rule.name(),
+                action: check.action,
+                detail,
+            });
+            verdict = verdict.max(Some(check.action));
+        }
+        verdict
+    }
+
+    /// Reports the hits of the last `evaluate` and flags their clients.
+    pub(crate) fn keep_hits(&mut self) {
+        for hit in self.pending.drain(..) {
+            self.flagged.insert(hit.client);
+            self.hits.push(hit);
+        }
+    }
+
+    /// Adds the applied `tx` to its client's history and keeps its hits.
+    pub(crate) fn record(&mut self, tx: &TransactionRecord) {
+        if self.config.checks.is_empty() {
+            return;
+        }
+        self.keep_hits();
+
+        let history = self.history.entry(tx.client).or_default();
+        history.push_back(Entry {
+            timestamp: tx.timestamp,
+            activity: activity(tx),
+        });
+
+        // Without timestamps on both ends a row can only be kept for the
+        // transaction windows.
+        while history.len() > self.max_transactions {
+            let oldest = history.front().and_then(|e| e.timestamp);
+            let expired = match (self.max_seconds, tx.timestamp, oldest) {
+                (Some(secs), Some(now), Some(oldest)) => oldest < now.saturating_sub(secs),
+                _ => true,
+            };
+            if !expired {
+                break;
+            }
+            history.pop_front();
+        }
+    }
+}
+
+fn activity(tx: &TransactionRecord) -> Activity {
+    match (tx.tx_type, tx.amount) {
+        (TransactionType::Deposit, Some(amount)) => Activity::Deposit(amount.into()),
+        (TransactionType::Withdrawal, Some(amount)) => Activity::Withdrawal(amount.into()),
+        (TransactionType::Dispute, _) => Activity::Dispute,
+        _ => Activity::Other,
+    }
+}
+
+fn in_window(history: Option<&VecDeque<Entry>>, candidate: Entry, window: Window) -> Vec<Entry> {
+    let history = history.into_iter().flatten();
+    let mut entries: Vec<Entry> = match window {
+        Window::Transactions(n) => {
+            let past: Vec<Entry> = history.copied().collect();
+            let skip = past.len().saturating_sub(n.saturating_sub(1));
+            past[skip..].to_vec()
+        }
+        Window::Seconds(secs) => match candidate.timestamp {
+            Some(now) => history
+                .filter(|e| e.timestamp.is_some_and(|t| t >= now.saturating_sub(secs)))
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        },
+    };
+    entries.push(candidate);
+    entries
+}
+
+fn triggered(
+    rule: &RiskRule,
+    entries: &[Entry],
+    candidate: Entry,
+    account: Option<&Account>,
+) -> Option<String> {
+    match (rule, candidate.activity) {
+        (RiskRule::MaxWithdrawals { count, .. }, Activity::Withdrawal(_)) => {
+            let seen = entries
+                .iter()
+                .filter(|e| matches!(e.activity, Activity::Withdrawal(_)))
+                .count();
+            (seen > *count).then(|| format!("{seen} withdrawals in window, limit {count}"))
+        }
+        (RiskRule::MaxWithdrawnAmount { amount, .. }, Activity::Withdrawal(_)) => {
+            let total = entries
+                .iter()
+                .filter_map(|e| match e.activity {
+                    Activity::Withdrawal(a) => Some(a),
+                    _ => None,
+                })
+                .try_fold(dec!(0), |sum, a| sum.checked_add(a))
+                .unwrap_or(Decimal::MAX);
+            (total > *amount).then(|| format!("{total} withdrawn in window, limit {amount}"))
+        }
+        (
+            RiskRule::DepositThenFullWithdrawal { tolerance, .. },
+            Activity::Withdrawal(withdrawn),
+        ) => {
+            let available = account.map_or(dec!(0), |a| a.available.into());
+            let deposited = entries
+                .iter()
+                .filter_map(|e| match e.activity {
+                    Activity::Deposit(a) => Some(a),
+                    _ => None,
+                })
+                .try_fold(dec!(0), |sum, a| sum.checked_add(a))
+                .unwrap_or(Decimal::MAX);
+            let empties = withdrawn <= available
+                && available
+                    .checked_sub(withdrawn)
+                    .is_some_and(|left| left <= *tolerance);
+            (deposited > dec!(0) && empties).then(|| {
+                format!(
+                    "withdrawal of {withdrawn} empties the account after depositing {deposited}"
+                )
+            })
+        }
+        (
+            RiskRule::DisputeRate {
+                max_rate,
+                min_disputes,
+                ..
+            },
+            Activity::Dispute,
+        ) => {
+            let disputes = entries
+                .iter()
+                .filter(|e| matches!(e.activity, Activity::Dispute))
+                .count();
+            let rate = Decimal::from(disputes) / Decimal::from(entries.len());
+            (disputes >= *min_disputes && rate > *max_rate).then(|| {
+                format!(
+                    "{disputes} disputes in {} transactions, limit rate {max_rate}",
+                    entries.len()
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::error::PaymentError;
+    use crate::transaction::TransactionInput;
+
+    fn timed(record: TransactionInput, timestamp: u64) -> TransactionInput {
+        TransactionInput {
+            timestamp: Some(timestamp),
+            ..record
+        }
+    }
+
+    fn engine(rule: RiskRule, action: RiskAction) -> PaymentEngine {
+        PaymentEngine::builder()
+            .risk(RiskConfig {
+                checks: vec![RiskCheck { rule, action }],
+            })
+            .build()
+    }
+
+    #[test]
+    fn max_withdrawals_rejects_over_limit() {
+        let rule = RiskRule::MaxWithdrawals {
+            count: 2,
+            window: Window::Transactions(4),
+        };
+        let mut engine = engine(rule, RiskAction::Reject);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+
+        let result = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            4,
+            Some(dec!(1)),
+        ));
+        assert!(matches!(result, Err(PaymentError::RiskRejected(TxId(4)))));
+        assert_eq!(engine.risk_report().len(), 1);
+        assert_eq!(engine.risk_report()[0].rule, "max_withdrawals");
+
+        // Other clients have their own history.
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                2,
+                5,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                2,
+                6,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+
+        // Once older withdrawals fall out of the window, client 1 may withdraw again.
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                7,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                8,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                9,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn max_withdrawn_amount_over_time_window() {
+        let rule = RiskRule::MaxWithdrawnAmount {
+            amount: dec!(100),
+            window: Window::Seconds(3600),
+        };
+        let mut engine = engine(rule, RiskAction::Reject);
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Deposit, 1, 1, Some(dec!(500))),
+                0,
+            ))
+            .unwrap();
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Withdrawal, 1, 2, Some(dec!(60))),
+                10,
+            ))
+            .unwrap();
+
+        let result = engine.process(&timed(
+            TransactionInput::new(TransactionType::Withdrawal, 1, 3, Some(dec!(50))),
+            20,
+        ));
+        assert!(matches!(result, Err(PaymentError::RiskRejected(_))));
+        engine
+            .process(&timed(
+                TransactionInput::new(TransactionType::Withdrawal, 1, 4, Some(dec!(50))),
+                3611,
+            ))
+            .unwrap();
+
+        let account = engine.accounts().next().unwrap();
+        assert_eq!(account.available, dec!(390));
+    }
+
+    #[test]
+    fn deposit_then_full_withdrawal_is_flagged() {
+        let rule = RiskRule::DepositThenFullWithdrawal {
+            window: Window::Transactions(2),
+            tolerance: dec!(0.01),
+        };
+        let mut engine = engine(rule, RiskAction::Flag);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(dec!(99.995)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                2,
+                3,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                2,
+                4,
+                Some(dec!(99.98)),
+            ))
+            .unwrap();
+
+        assert!(engine.is_flagged(ClientId(1)));
+        assert!(!engine.is_flagged(ClientId(2)));
+        let hit = &engine.risk_report()[0];
+        assert_eq!(
+            (hit.client, hit.tx, hit.action),
+            (ClientId(1), TxId(2), RiskAction::Flag)
+        );
+        assert_eq!(
+            engine
+                .accounts()
+                .find(|a| a.client == ClientId(1))
+                .unwrap()
+                .available,
+            dec!(0.005)
+        );
+    }
+
+    #[test]
+    fn rejected_transactions_leave_no_hits() {
+        let rule = RiskRule::MaxWithdrawals {
+            count: 0,
+            window: Window::Transactions(1),
+        };
+        let mut engine = engine(rule, RiskAction::Flag);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+        let result = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(dec!(150)),
+        ));
+
+        assert!(matches!(result, Err(PaymentError::InsufficientFunds(..))));
+        assert!(engine.risk_report().is_empty());
+        assert!(!engine.is_flagged(ClientId(1)));
+    }
+
+    #[test]
+    fn rules_never_see_invalid_amounts() {
+        let rule = RiskRule::DepositThenFullWithdrawal {
+            window: Window::Transactions(2),
+            tolerance: dec!(0),
+        };
+        let mut engine = engine(rule, RiskAction::Flag);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::MAX),
+            ))
+            .unwrap();
+        let result = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(-Decimal::MAX),
+        ));
+
+        assert!(matches!(
+            result,
+            Err(PaymentError::InvalidAmount(TxId(2), _))
+        ));
+        assert!(engine.risk_report().is_empty());
+    }
+
+    #[test]
+    fn untimed_rows_are_pruned_by_count() {
+        let mut risk = RiskEngine::new(RiskConfig {
+            checks: vec![RiskCheck {
+                rule: RiskRule::MaxWithdrawals {
+                    count: 1,
+                    window: Window::Seconds(60),
+                },
+                action: RiskAction::Flag,
+            }],
+        });
+        for tx in 1..=10 {
+            risk.record(&TransactionRecord::new(
+                TransactionType::Deposit,
+                1,
+                tx,
+                Some(dec!(1).into()),
+            ));
+        }
+
+        assert!(risk.history[&ClientId(1)].is_empty());
+    }
+
+    #[test]
+    fn dispute_rate_spike_freezes_account() {
+        let rule = RiskRule::DisputeRate {
+            max_rate: dec!(0.4),
+            min_disputes: 2,
+            window: Window::Transactions(4),
+        };
+        let mut engine = engine(rule, RiskAction::Freeze);
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                2,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(TransactionType::Dispute, 1, 1, None))
+            .unwrap();
+
+        let result = engine.process(&TransactionInput::new(TransactionType::Dispute, 1, 2, None));
+        assert!(matches!(
+            result,
+            Err(PaymentError::RiskFrozen(ClientId(1), TxId(2)))
+        ));
+        let account = engine.accounts().next().unwrap();
+        assert!(account.locked);
+        assert_eq!(account.held, dec!(10));
+        assert!(
+            engine
+                .process(&TransactionInput::new(
+                    TransactionType::Deposit,
+                    1,
+                    3,
+                    Some(dec!(1))
+                ))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn strongest_action_wins() {
+        let mut engine = PaymentEngine::builder()
+            .risk(RiskConfig {
+                checks: vec![
+                    RiskCheck {
+                        rule: RiskRule::MaxWithdrawals {
+                            count: 0,
+                            window: Window::Transactions(1),
+                        },
+                        action: RiskAction::Flag,
+                    },
+                    RiskCheck {
+                        rule: RiskRule::MaxWithdrawnAmount {
+                            amount: dec!(5),
+                            window: Window::Transactions(1),
+                        },
+                        action: RiskAction::Reject,
+                    },
+                ],
+            })
+            .build();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .unwrap();
+        engine
+            .process(&TransactionInput::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        assert!(
+            engine
+                .process(&TransactionInput::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    3,
+                    Some(dec!(6))
+                ))
+                .is_err()
+        );
+
+        let actions: Vec<RiskAction> = engine.risk_report().iter().map(|h| h.action).collect();
+        assert_eq!(
+            actions,
+            [RiskAction::Flag, RiskAction::Flag, RiskAction::Reject]
+        );
+    }
+
+    #[test]
+    fn config_from_toml() {
+        let config = RiskConfig::from_toml(
+            r#"
+            [[checks]]
+            rule = "max_withdrawals"
+            count = 3
+            window = { transactions = 10 }
+            action = "reject"
+
+            [[checks]]
+            rule = "max_withdrawn_amount"
+            amount = "1000.50"
+            window = { seconds = 86400 }
+            action = "flag"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.checks,
+            [
+                RiskCheck {
+                    rule: RiskRule::MaxWithdrawals {
+                        count: 3,
+                        window: Window::Transactions(10),
+                    },
+                    action: RiskAction::Reject,
+                },
+                RiskCheck {
+                    rule: RiskRule::MaxWithdrawnAmount {
+                        amount: dec!(1000.50),
+                        window: Window::Seconds(86400),
+                    },
+                    action: RiskAction::Flag,
+                },
+            ]
+        );
+    }
+}
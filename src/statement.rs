@@ -0,0 +1,297 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::error::PaymentError;
+use crate::events::{Balances, Event, EventKind};
+use crate::transaction::{ClientId, TxId};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatementFormat {
+    Csv,
+    Json,
+    #[default]
+    Text,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            other => Err(format!(
+                "unknown statement format `{other}` (expected csv, json or text)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeChange {
+    Opened,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeChange {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Opened => "opened",
+            Self::Resolved => "resolved",
+            Self::ChargedBack => "charged_back",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StatementBalance {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl StatementBalance {
+    /// Fails with `BalanceOverflow` if `client`'s total does not fit.
+    pub fn new(client: ClientId, balances: Balances) -> Result<Self, PaymentError> {
+        Ok(Self {
+            available: balances.available,
+            held: balances.held,
+            total: balances
+                .total()
+                .ok_or(PaymentError::BalanceOverflow(client))?,
+            locked: balances.locked,
+        })
+    }
+}
+
+/// One balance-affecting event, with the client's balances right after it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatementLine {
+    pub seq: u64,
+    pub tx: TxId,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub dispute: Option<DisputeChange>,
+    /// Input line of the originating transaction, such as `input.csv:12 (byte 345)`.
+    pub source: Option<String>,
+}
+
+impl TryFrom<&Event> for StatementLine {
+    type Error = PaymentError;
+
+    fn try_from(event: &Event) -> Result<Self, PaymentError> {
+        let dispute = match event.kind {
+            EventKind::DisputeOpened { .. } => Some(DisputeChange::Opened),
+            EventKind::DisputeResolved { .. } => Some(DisputeChange::Resolved),
+            EventKind::ChargedBack { .. } => Some(DisputeChange::ChargedBack),
+            _ => None,
+        };
+        Ok(Self {
+            seq: event.seq,
+            tx: event.tx,
+            kind: event.kind.name(),
+            amount: event.kind.amount(),
+            available: event.after.available,
+            held: event.after.held,
+            total: event
+                .after
+                .total()
+                .ok_or(PaymentError::BalanceOverflow(event.client))?,
+            dispute,
+            source: event.provenance.as_ref().map(ToString::to_string),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Statement {
+    pub client: ClientId,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub opening: StatementBalance,
+    pub closing: StatementBalance,
+    pub lines: Vec<StatementLine>,
+}
+
+impl Statement {
+    /// Builds a statement for `client` from an event log, covering sequence
+    /// numbers `from..=to`. Rejected and replayed transactions leave balances
+    /// untouched and are left out. Fails with `BalanceOverflow` if a total in
+    /// the statement does not fit.
+    pub fn from_events<'a>(
+        client: ClientId,
+        events: impl IntoIterator<Item = &'a Event>,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Self, PaymentError> {
+        let mut opening = Balances::default();
+        let mut closing = None;
+        let mut lines = Vec::new();
+
+        for event in events {
+            if event.client != client
+                || matches!(
+                    event.kind,
+                    EventKind::Rejected { .. } | EventKind::Replayed { .. }
+                )
+            {
+                continue;
+            }
+            if from.is_some_and(|from| event.seq < from) {
+                opening = event.after;
+                continue;
+            }
+            if to.is_some_and(|to| event.seq > to) {
+                break;
+            }
+            lines.push(StatementLine::try_from(event)?);
+            closing = Some(event.after);
+        }
+
+        Ok(Self {
+            client,
+            from,
+            to,
+            opening: StatementBalance::new(client, opening)?,
+            closing: StatementBalance::new(client, closing.unwrap_or(opening))?,
+            lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::transaction::{TransactionInput, TransactionType};
+    use rust_decimal::dec;
+    use std::sync::mpsc;
+
+    fn events() -> Vec<Event> {
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentEngine::builder().subscriber(sender).build();
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(10)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            2,
+            2,
+            Some(dec!(7)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            3,
+            Some(dec!(4)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Withdrawal,
+            1,
+            4,
+            Some(dec!(100)),
+        ));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Deposit,
+            1,
+            5,
+            Some(dec!(3)),
+        ));
+        let _ = engine.process(&TransactionInput::new(TransactionType::Dispute, 1, 5, None));
+        let _ = engine.process(&TransactionInput::new(
+            TransactionType::Chargeback,
+            1,
+            5,
+            None,
+        ));
+        drop(engine);
+        receiver.try_iter().collect()
+    }
+
+    fn summary(statement: &Statement) -> Vec<(u64, &'static str, Option<DisputeChange>)> {
+        statement
+            .lines
+            .iter()
+            .map(|line| (line.seq, line.kind, line.dispute))
+            .collect()
+    }
+
+    #[test]
+    fn full_statement_tracks_running_balances() {
+        let statement = Statement::from_events(ClientId(1), &events(), None, None).unwrap();
+
+        assert_eq!(
+            summary(&statement),
+            [
+                (1, "deposited", None),
+                (3, "withdrawn", None),
+                (5, "deposited", None),
+                (6, "dispute_opened", Some(DisputeChange::Opened)),
+                (7, "charged_back", Some(DisputeChange::ChargedBack)),
+                (7, "account_locked", None),
+            ]
+        );
+        let opened = &statement.lines[3];
+        assert_eq!((opened.available, opened.held), (dec!(6), dec!(3)));
+        assert_eq!(statement.opening.total, dec!(0));
+        assert_eq!(statement.closing.total, dec!(6));
+        assert!(statement.closing.locked);
+    }
+
+    #[test]
+    fn range_sets_opening_balance() {
+        let statement = Statement::from_events(ClientId(1), &events(), Some(4), Some(6)).unwrap();
+
+        assert_eq!(
+            summary(&statement),
+            [
+                (5, "deposited", None),
+                (6, "dispute_opened", Some(DisputeChange::Opened)),
+            ]
+        );
+        assert_eq!(statement.opening.available, dec!(6));
+        assert_eq!(statement.closing.available, dec!(6));
+        assert_eq!(statement.closing.held, dec!(3));
+        assert!(!statement.closing.locked);
+    }
+
+    #[test]
+    fn empty_range_carries_opening_to_closing() {
+        let statement = Statement::from_events(ClientId(2), &events(), Some(3), None).unwrap();
+
+        assert!(statement.lines.is_empty());
+        assert_eq!(statement.opening, statement.closing);
+        assert_eq!(statement.closing.total, dec!(7));
+    }
+
+    #[test]
+    fn overflowing_total_is_an_error() {
+        let mut events = events();
+        events[0].after.available = Decimal::MAX;
+        events[0].after.held = Decimal::MAX;
+
+        let result = Statement::from_events(ClientId(1), &events, None, None);
+        assert!(matches!(
+            result,
+            Err(PaymentError::BalanceOverflow(ClientId(1)))
+        ));
+        let result = Statement::from_events(ClientId(1), &events, Some(2), Some(2));
+        assert!(matches!(
+            result,
+            Err(PaymentError::BalanceOverflow(ClientId(1)))
+        ));
+    }
+}
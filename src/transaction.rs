@@ -1,7 +1,66 @@
+use std::fmt;
+use std::sync::Arc;
+
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::amount::Amount;
+
+macro_rules! id_type {
+    ($name:ident, $repr:ty, $what:literal) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+        #[serde(transparent)]
+        pub struct $name(pub $repr);
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct IdVisitor;
+
+                fn invalid<E: de::Error>(v: impl fmt::Display) -> E {
+                    E::custom(format_args!(
+                        "invalid {} `{v}`: expected an unsigned integer up to {}",
+                        $what,
+                        <$repr>::MAX
+                    ))
+                }
+
+                impl Visitor<'_> for IdVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        write!(f, "an unsigned integer {} up to {}", $what, <$repr>::MAX)
+                    }
+
+                    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                        <$repr>::try_from(v).map($name).map_err(|_| invalid(v))
+                    }
+
+                    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                        <$repr>::try_from(v).map($name).map_err(|_| invalid(v))
+                    }
+
+                    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                        v.parse().map($name).map_err(|_| invalid(v))
+                    }
+                }
+
+                deserializer.deserialize_any(IdVisitor)
+            }
+        }
+    };
+}
+
+id_type!(ClientId, u64, "client id");
+id_type!(TxId, u64, "transaction id");
 
-#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -9,13 +68,124 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    Refund,
+    Open,
+    Close,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct TransactionRecord {
+impl TransactionType {
+    pub const ALL: [Self; 11] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::Authorize,
+        Self::Capture,
+        Self::Void,
+        Self::Refund,
+        Self::Open,
+        Self::Close,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Authorize => "authorize",
+            Self::Capture => "capture",
+            Self::Void => "void",
+            Self::Refund => "refund",
+            Self::Open => "open",
+            Self::Close => "close",
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a record came from in its input file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<Arc<str>>,
+    /// 1-based line number, as reported by the csv reader.
+    pub line: u64,
+    /// Offset of the record's first byte from the start of the input.
+    pub byte: u64,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{file}:{} (byte {})", self.line, self.byte),
+            None => write!(f, "line {} (byte {})", self.line, self.byte),
+        }
+    }
+}
+
+/// A transaction as the engine applies it, with its amount as an [`Amount`].
+///
+/// Input is read as a [`TransactionInput`], whose amount is the `Decimal`
+/// from the file: the engine's precision policy has to see digits past the
+/// fourth to reject or round them before the amount becomes an `Amount`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransactionRecord<A = Amount> {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
-    pub client: u16,
-    pub tx: u32,
-    pub amount: Option<Decimal>,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<A>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Free-form note carried by `open` and `close` into their events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    /// Set by the CSV reader; never read from or written to a column.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// A transaction as read from input, before the precision policy has run.
+pub type TransactionInput = TransactionRecord<Decimal>;
+
+impl<A> TransactionRecord<A> {
+    /// The same transaction with a different amount.
+    pub fn with_amount<B>(&self, amount: Option<B>) -> TransactionRecord<B> {
+        TransactionRecord {
+            tx_type: self.tx_type,
+            client: self.client,
+            tx: self.tx,
+            amount,
+            timestamp: self.timestamp,
+            metadata: self.metadata.clone(),
+            provenance: self.provenance.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<A> TransactionRecord<A> {
+    /// A record with no timestamp, metadata or provenance.
+    pub(crate) fn new(tx_type: TransactionType, client: u64, tx: u64, amount: Option<A>) -> Self {
+        Self {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount,
+            timestamp: None,
+            metadata: None,
+            provenance: None,
+        }
+    }
 }